@@ -0,0 +1,220 @@
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+}
+
+impl Date {
+    // The date in the local time zone.
+    pub fn today() -> Date {
+        Date::from_days(local_seconds().div_euclid(86400))
+    }
+
+    // Days since 1970-01-01 to a civil date (Howard Hinnant's algorithm).
+    pub fn from_days(days: i64) -> Date {
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        Date { year, month, day }
+    }
+
+    pub fn to_days(&self) -> i64 {
+        let year = if self.month <= 2 { self.year - 1 } else { self.year };
+        let era = year.div_euclid(400);
+        let yoe = year.rem_euclid(400);
+        let mp = if self.month > 2 { self.month - 3 } else { self.month + 9 } as i64;
+        let doy = (153 * mp + 2) / 5 + self.day as i64 - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+        era * 146097 + doe - 719468
+    }
+
+    pub fn add_days(&self, days: i64) -> Date {
+        Date::from_days(self.to_days() + days)
+    }
+
+    // Day of the week, 0 for Monday through 6 for Sunday.
+    pub fn weekday(&self) -> usize {
+        (self.to_days() + 3).rem_euclid(7) as usize
+    }
+
+    pub fn parse(s: &str) -> Option<Date> {
+        let mut parts = s.splitn(3, '-');
+        let year = parts.next()?.parse().ok()?;
+        let month = parts.next()?.parse().ok()?;
+        let day = parts.next()?.parse().ok()?;
+        if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+            return None;
+        }
+
+        Some(Date { year, month, day })
+    }
+}
+
+// Seconds since the epoch moved into the local time zone, so whole days of
+// it fall on local midnights.
+fn local_seconds() -> i64 {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+    secs + utc_offset(secs)
+}
+
+// How far local time is ahead of UTC at `secs`, daylight saving included.
+#[cfg(unix)]
+fn utc_offset(secs: i64) -> i64 {
+    let time = secs as libc::time_t;
+    // SAFETY: `tm` is plain data that localtime_r fills in, or leaves alone on failure
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return 0;
+    }
+    tm.tm_gmtoff as i64
+}
+
+#[cfg(not(unix))]
+fn utc_offset(_secs: i64) -> i64 {
+    0
+}
+
+// Seconds since local midnight, the time of day on the wall clock.
+pub fn seconds_today() -> u32 {
+    local_seconds().rem_euclid(86400) as u32
+}
+
+static MONTHS: [&str; 12] = ["January", "February", "March", "April", "May", "June", "July", "August", "September", "October", "November", "December"];
+static WEEKDAYS: [&str; 7] = ["Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday", "Sunday"];
+
+// Formats a date and time of day with the common strftime directives.
+pub fn strftime(date: Date, seconds: u32, format: &str) -> String {
+    let mut out = String::new();
+    let mut chars = format.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => out.push_str(&format!("{:04}", date.year)),
+            Some('y') => out.push_str(&format!("{:02}", date.year.rem_euclid(100))),
+            Some('m') => out.push_str(&format!("{:02}", date.month)),
+            Some('d') => out.push_str(&format!("{:02}", date.day)),
+            Some('e') => out.push_str(&format!("{:2}", date.day)),
+            Some('H') => out.push_str(&format!("{:02}", seconds / 3600)),
+            Some('M') => out.push_str(&format!("{:02}", seconds / 60 % 60)),
+            Some('S') => out.push_str(&format!("{:02}", seconds % 60)),
+            Some('B') => out.push_str(MONTHS[date.month as usize - 1]),
+            Some('b') => out.push_str(&MONTHS[date.month as usize - 1][..3]),
+            Some('A') => out.push_str(WEEKDAYS[date.weekday()]),
+            Some('a') => out.push_str(&WEEKDAYS[date.weekday()][..3]),
+            Some('F') => out.push_str(&date.to_string()),
+            Some('%') => out.push('%'),
+            Some(other) => {
+                out.push('%');
+                out.push(other);
+            },
+            None => out.push('%'),
+        }
+    }
+
+    out
+}
+
+pub fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if (year % 4 == 0 && year % 100 != 0) || year % 400 == 0 => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> Date {
+        Date::parse(s).unwrap()
+    }
+
+    #[test]
+    fn days_round_trip_across_leap_years_and_epochs() {
+        assert_eq!(date("1970-01-01").to_days(), 0);
+        assert_eq!(date("2000-01-01").to_days(), 10957);
+        assert_eq!(Date::from_days(-1).to_string(), "1969-12-31");
+        assert_eq!(date("2000-02-28").add_days(1).to_string(), "2000-02-29");
+        assert_eq!(date("1900-02-28").add_days(1).to_string(), "1900-03-01");
+        assert_eq!(date("2024-12-31").add_days(1).to_string(), "2025-01-01");
+
+        for days in -800..12000 {
+            let d = Date::from_days(days);
+            assert_eq!(d.to_days(), days);
+            assert_eq!(date(&d.to_string()).to_days(), days);
+        }
+    }
+
+    #[test]
+    fn weekdays_of_known_dates() {
+        // 1970-01-01 was a Thursday, 2000-01-01 a Saturday, 2024-02-29 a Thursday
+        assert_eq!(date("1970-01-01").weekday(), 3);
+        assert_eq!(date("2000-01-01").weekday(), 5);
+        assert_eq!(date("2024-02-29").weekday(), 3);
+        assert_eq!(date("1969-12-28").weekday(), 6);
+    }
+
+    #[test]
+    fn parse_rejects_days_and_months_that_do_not_exist() {
+        assert!(Date::parse("2024-02-29").is_some());
+        assert!(Date::parse("2023-02-29").is_none());
+        assert!(Date::parse("2024-02-30").is_none());
+        assert!(Date::parse("2024-13-01").is_none());
+        assert!(Date::parse("2024-00-10").is_none());
+        assert!(Date::parse("2024-04-31").is_none());
+        assert!(Date::parse("2024-04").is_none());
+        assert!(Date::parse("notes").is_none());
+    }
+
+    #[test]
+    fn strftime_specifiers() {
+        let d = date("2024-03-05");
+        let secs = 9 * 3600 + 7 * 60 + 3;
+        let cases = [
+            ("%Y", "2024"),
+            ("%y", "24"),
+            ("%m", "03"),
+            ("%d", "05"),
+            ("%e", " 5"),
+            ("%H", "09"),
+            ("%M", "07"),
+            ("%S", "03"),
+            ("%B", "March"),
+            ("%b", "Mar"),
+            ("%A", "Tuesday"),
+            ("%a", "Tue"),
+            ("%F", "2024-03-05"),
+            ("%%", "%"),
+            ("%q", "%q"),
+            ("100%", "100%"),
+            ("%d/%m at %H:%M", "05/03 at 09:07"),
+        ];
+        for (format, expected) in cases {
+            assert_eq!(strftime(d, secs, format), expected, "{}", format);
+        }
+    }
+}
@@ -0,0 +1,690 @@
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use regex::Regex;
+
+use crate::highlight::{highlight, Token};
+use crate::markdown::{code_lines, front_matter_len, headings, is_fence, is_setext_underline, list_prefix, parse_line, parse_lines_to_markdown, render_line, Conceal, Marker, Part};
+use crate::screen::{hanging_indent, slice_columns, strip_escapes, visible_width, wrap_columns};
+use crate::table::{is_table_row, Table};
+use crate::terminal::{read_lines, Line};
+
+const STYLE: &str = r#"
+body { max-width: 46em; margin: 2em auto; padding: 0 1em; font: 16px/1.6 -apple-system, "Segoe UI", Helvetica, Arial, sans-serif; color: #24292f; }
+h1, h2 { border-bottom: 1px solid #d0d7de; padding-bottom: .3em; }
+h1 a.anchor, h2 a.anchor, h3 a.anchor, h4 a.anchor, h5 a.anchor, h6 a.anchor { visibility: hidden; margin-left: -1em; padding-right: .2em; color: #57606a; text-decoration: none; }
+h1:hover a.anchor, h2:hover a.anchor, h3:hover a.anchor, h4:hover a.anchor, h5:hover a.anchor, h6:hover a.anchor { visibility: visible; }
+a { color: #0969da; }
+code { font-family: ui-monospace, SFMono-Regular, Menlo, Consolas, monospace; background: #f6f8fa; padding: .1em .3em; border-radius: 4px; }
+pre { background: #f6f8fa; padding: 1em; overflow: auto; border-radius: 6px; }
+pre code { padding: 0; background: none; }
+blockquote { margin: 0; padding: 0 1em; color: #57606a; border-left: .25em solid #d0d7de; }
+table { border-collapse: collapse; }
+th, td { border: 1px solid #d0d7de; padding: .3em .8em; }
+nav.toc { background: #f6f8fa; padding: .5em 1em; border-radius: 6px; }
+nav.toc ul { padding-left: 1.2em; }
+li.task { list-style: none; margin-left: -1.2em; }
+.kw { color: #cf222e; } .str { color: #0a3069; } .num { color: #0550ae; } .com { color: #6e7781; font-style: italic; }
+.tag { color: #8250df; }
+"#;
+
+// Where links are resolved from and written relative to.
+pub struct Context {
+    source_dir: PathBuf,
+    output_dir: PathBuf,
+}
+
+impl Context {
+    pub fn new(source: Option<&str>, output: &str) -> Context {
+        let source_dir = source.map(|s| parent_dir(Path::new(s))).unwrap_or_default();
+        let output_dir = parent_dir(Path::new(output));
+        Context { source_dir, output_dir }
+    }
+
+    // Relative links are rewritten so they still point at the same file from the output's directory.
+    // Links to other notes go to their exported pages, which sit next to the notes.
+    fn link(&self, url: &str) -> String {
+        if url.contains("://") || url.starts_with('#') || url.starts_with('/') || url.starts_with("mailto:") {
+            return url.to_string();
+        }
+
+        let (path, fragment) = match url.split_once('#') {
+            Some((path, fragment)) => (path, format!("#{}", fragment)),
+            None => (url, String::new()),
+        };
+        let mut target = absolute(&self.source_dir.join(path));
+        if target.extension().is_some_and(|ext| ext == "md") {
+            target.set_extension("html");
+        }
+        let rel = relative_path(&absolute(&self.output_dir), &target);
+
+        format!("{}{}", rel.to_string_lossy().replace('\\', "/"), fragment)
+    }
+}
+
+fn parent_dir(path: &Path) -> PathBuf {
+    path.parent().map(|p| p.to_path_buf()).unwrap_or_default()
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    let path = if path.is_absolute() { path.to_path_buf() } else { env::current_dir().unwrap_or_default().join(path) };
+
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => { normal.pop(); },
+            other => normal.push(other),
+        }
+    }
+    normal
+}
+
+fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let from: Vec<Component> = from.components().collect();
+    let to: Vec<Component> = to.components().collect();
+    let common = from.iter().zip(to.iter()).take_while(|(a, b)| a == b).count();
+
+    let mut rel = PathBuf::new();
+    for _ in common..from.len() {
+        rel.push("..");
+    }
+    for component in &to[common..] {
+        rel.push(component);
+    }
+    rel
+}
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+pub fn slug(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.to_lowercase().chars() {
+        if c.is_alphanumeric() || c == '_' {
+            slug.push(c);
+        } else if (c == ' ' || c == '-') && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_matches('-').to_string()
+}
+
+// Unique anchors for every heading, in document order.
+pub fn heading_slugs(rows: &[Line]) -> Vec<String> {
+    let mut seen: Vec<String> = Vec::new();
+    headings(rows).iter().map(|h| {
+        let base = slug(&strip_markup(&h.text));
+        let mut candidate = base.clone();
+        let mut n = 1;
+        while seen.contains(&candidate) {
+            candidate = format!("{}-{}", base, n);
+            n += 1;
+        }
+        seen.push(candidate.clone());
+        candidate
+    }).collect()
+}
+
+fn strip_markup(text: &str) -> String {
+    parse_line(text).spans.iter()
+        .filter(|span| matches!(span.part, Part::Text(_)))
+        .map(|span| &text[span.start..span.end])
+        .collect()
+}
+
+// Inline content of `row` from byte `from` on.
+fn inline_html(row: &str, from: usize, ctx: &Context) -> String {
+    let mut html = String::new();
+    for span in parse_line(row).spans.iter().filter(|span| span.end > from) {
+        if !matches!(span.part, Part::Text(_)) {
+            continue;
+        }
+
+        let text = escape(&row[span.start.max(from)..span.end]);
+        let url = span.url.map(|(start, end)| escape(&ctx.link(&row[start..end]))).unwrap_or_default();
+        match span.tag {
+            Some("a") => html.push_str(&format!("<a href=\"{}\">{}</a>", url, text)),
+            Some("img") => html.push_str(&format!("<img src=\"{}\" alt=\"{}\">", url, text)),
+            Some("wiki") => {
+                let target = span.url.map_or(&row[span.start..span.end], |(start, end)| &row[start..end]);
+                let (note, heading) = target.split_once('#').unwrap_or((target, ""));
+                let mut href = ctx.link(&format!("{}.md", note.trim())).replace(' ', "%20");
+                if !heading.is_empty() {
+                    href = format!("{}#{}", href, slug(heading));
+                }
+                html.push_str(&format!("<a href=\"{}\">{}</a>", escape(&href), text));
+            },
+            // The rule also captures the space before a tag
+            Some("tag") if text.trim().is_empty() => html.push_str(&text),
+            Some("tag") => html.push_str(&format!("<span class=\"tag\">{}</span>", text)),
+            Some(tag) => html.push_str(&format!("<{}>{}</{}>", tag, text, tag)),
+            None => html.push_str(&text),
+        }
+    }
+    html
+}
+
+fn code_html(line: &str, lang: &str) -> String {
+    highlight(line, lang).iter().map(|(token, text)| {
+        let class = match token {
+            Token::Keyword => "kw",
+            Token::String => "str",
+            Token::Number => "num",
+            Token::Comment => "com",
+            Token::Plain => return escape(text),
+        };
+        format!("<span class=\"{}\">{}</span>", class, escape(text))
+    }).collect()
+}
+
+fn toc_html(rows: &[Line]) -> String {
+    let headings = headings(rows);
+    if headings.len() < 2 {
+        return String::new();
+    }
+
+    let mut html = String::from("<nav class=\"toc\">\n");
+    let mut levels: Vec<usize> = Vec::new();
+    for (heading, slug) in headings.iter().zip(heading_slugs(rows)) {
+        while levels.last().is_some_and(|l| *l > heading.level) {
+            levels.pop();
+            html.push_str("</li></ul>\n");
+        }
+        if levels.last() == Some(&heading.level) {
+            html.push_str("</li>\n");
+        } else {
+            levels.push(heading.level);
+            html.push_str("<ul>\n");
+        }
+        html.push_str(&format!("<li><a href=\"#{}\">{}</a>", slug, escape(&strip_markup(&heading.text))));
+    }
+    for _ in levels {
+        html.push_str("</li></ul>\n");
+    }
+    html.push_str("</nav>\n");
+
+    html
+}
+
+// Open lists, innermost last, with their indentation and closing tag.
+struct Lists(Vec<(usize, &'static str)>);
+
+impl Lists {
+    fn close_to(&mut self, indent: usize, html: &mut String) {
+        while self.0.last().is_some_and(|(i, _)| *i > indent) {
+            let (_, tag) = self.0.pop().unwrap();
+            html.push_str(&format!("</li></{}>\n", tag));
+        }
+    }
+
+    fn close_all(&mut self, html: &mut String) {
+        while let Some((_, tag)) = self.0.pop() {
+            html.push_str(&format!("</li></{}>\n", tag));
+        }
+    }
+}
+
+pub fn body_html(rows: &[Line], ctx: &Context) -> String {
+    let headings = headings(rows);
+    let slugs = heading_slugs(rows);
+    let code = code_lines(rows);
+    let rule = Regex::new(r#"^\s*([-*_])(\s*([-*_])){2,}\s*$"#).unwrap();
+    let closing = Regex::new(r#"\s+#+\s*$"#).unwrap();
+
+    let mut html = String::new();
+    let mut paragraph: Vec<String> = Vec::new();
+    let mut quote: Vec<String> = Vec::new();
+    let mut lists = Lists(Vec::new());
+
+    let flush = |html: &mut String, paragraph: &mut Vec<String>, quote: &mut Vec<String>| {
+        if !paragraph.is_empty() {
+            html.push_str(&format!("<p>{}</p>\n", paragraph.join("\n")));
+            paragraph.clear();
+        }
+        if !quote.is_empty() {
+            html.push_str(&format!("<blockquote><p>{}</p></blockquote>\n", quote.join("\n")));
+            quote.clear();
+        }
+    };
+
+    // Front matter is metadata, not content
+    let mut i = front_matter_len(rows);
+    while i < rows.len() {
+        let row = &rows[i].row;
+
+        if code[i] && is_fence(row) {
+            flush(&mut html, &mut paragraph, &mut quote);
+            lists.close_all(&mut html);
+
+            let lang = row.trim_start().trim_start_matches(['`', '~']).trim().to_string();
+            let class = if lang.is_empty() { String::new() } else { format!(" class=\"language-{}\"", escape(&lang)) };
+            html.push_str(&format!("<pre><code{}>", class));
+            i += 1;
+            while i < rows.len() && !(code[i] && is_fence(&rows[i].row)) {
+                html.push_str(&code_html(&rows[i].row, &lang));
+                html.push('\n');
+                i += 1;
+            }
+            html.push_str("</code></pre>\n");
+            i += 1;
+            continue;
+        }
+
+        if let Some(h) = headings.iter().position(|h| h.line == i) {
+            flush(&mut html, &mut paragraph, &mut quote);
+            lists.close_all(&mut html);
+
+            let heading = &headings[h];
+            let setext = is_setext_underline(rows, i + 1);
+            let content = if setext { inline_html(row.trim(), 0, ctx) } else { inline_html(row, 0, ctx) };
+            html.push_str(&format!(
+                "<h{0} id=\"{1}\"><a class=\"anchor\" href=\"#{1}\">#</a>{2}</h{0}>\n",
+                heading.level, slugs[h], closing.replace(&content, "")
+            ));
+            i += if setext { 2 } else { 1 };
+            continue;
+        }
+
+        if row.trim().is_empty() {
+            flush(&mut html, &mut paragraph, &mut quote);
+            i += 1;
+            continue;
+        }
+
+        if is_table_row(row) {
+            flush(&mut html, &mut paragraph, &mut quote);
+            lists.close_all(&mut html);
+
+            let start = i;
+            while i < rows.len() && is_table_row(&rows[i].row) {
+                i += 1;
+            }
+            let lines: Vec<String> = rows[start..i].iter().map(|line| line.row.clone()).collect();
+            html.push_str(&table_html(&Table::parse(&lines), ctx));
+            continue;
+        }
+
+        if rule.is_match(row) {
+            flush(&mut html, &mut paragraph, &mut quote);
+            lists.close_all(&mut html);
+            html.push_str("<hr>\n");
+            i += 1;
+            continue;
+        }
+
+        match list_prefix(row) {
+            Some(prefix) if prefix.marker == Marker::Quote => {
+                if !paragraph.is_empty() {
+                    flush(&mut html, &mut paragraph, &mut quote);
+                }
+                lists.close_all(&mut html);
+                quote.push(inline_html(row, prefix.len, ctx));
+            },
+            Some(prefix) => {
+                flush(&mut html, &mut paragraph, &mut quote);
+                let indent = prefix.indent.len();
+                let tag = if matches!(prefix.marker, Marker::Ordered(..)) { "ol" } else { "ul" };
+                lists.close_to(indent, &mut html);
+
+                match lists.0.last() {
+                    Some((i, t)) if *i == indent && *t == tag => html.push_str("</li>\n"),
+                    Some((i, t)) if *i == indent => {
+                        html.push_str(&format!("</li></{}>\n", t));
+                        lists.0.pop();
+                        lists.0.push((indent, tag));
+                        html.push_str(&open_list(&prefix.marker));
+                    },
+                    _ => {
+                        lists.0.push((indent, tag));
+                        html.push_str(&open_list(&prefix.marker));
+                    },
+                }
+
+                let content = inline_html(row, prefix.len, ctx);
+                match prefix.marker {
+                    Marker::Task(_) => {
+                        let checked = row[..prefix.len].contains(['x', 'X']);
+                        html.push_str(&format!(
+                            "<li class=\"task\"><input type=\"checkbox\" disabled{}> {}",
+                            if checked { " checked" } else { "" }, content
+                        ));
+                    },
+                    _ => html.push_str(&format!("<li>{}", content)),
+                }
+            },
+            None if !lists.0.is_empty() && row.starts_with([' ', '\t']) => {
+                html.push_str(&format!("\n{}", inline_html(row.trim_start(), 0, ctx)));
+            },
+            None => {
+                lists.close_all(&mut html);
+                if !quote.is_empty() {
+                    flush(&mut html, &mut paragraph, &mut quote);
+                }
+                paragraph.push(inline_html(row, 0, ctx));
+            },
+        }
+        i += 1;
+    }
+
+    flush(&mut html, &mut paragraph, &mut quote);
+    lists.close_all(&mut html);
+
+    html
+}
+
+fn open_list(marker: &Marker) -> String {
+    match marker {
+        Marker::Ordered(1, _) => "<ol>\n".to_string(),
+        Marker::Ordered(n, _) => format!("<ol start=\"{}\">\n", n),
+        _ => "<ul>\n".to_string(),
+    }
+}
+
+fn table_html(table: &Table, ctx: &Context) -> String {
+    let mut html = String::from("<table>\n");
+    for (r, row) in table.rows.iter().enumerate() {
+        let cell = if r == 0 { "th" } else { "td" };
+        html.push_str("<tr>");
+        for text in row {
+            html.push_str(&format!("<{0}>{1}</{0}>", cell, inline_html(text, 0, ctx)));
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</table>\n");
+
+    html
+}
+
+pub fn to_html(rows: &[Line], title: &str, ctx: &Context) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n{}{}</body>\n</html>\n",
+        escape(title), STYLE, toc_html(rows), body_html(rows, ctx)
+    )
+}
+
+// The first H1, falling back to the file name.
+pub fn title(rows: &[Line], filename: Option<&str>) -> String {
+    if let Some(h) = headings(rows).iter().find(|h| h.level == 1) {
+        return strip_markup(&h.text);
+    }
+    filename.and_then(|f| Path::new(f).file_stem()).map_or("Untitled".to_string(), |s| s.to_string_lossy().to_string())
+}
+
+pub fn export_html(rows: &[Line], source: Option<&str>, output: &str) -> Result<(), Box<dyn Error>> {
+    let ctx = Context::new(source, output);
+    fs::write(output, to_html(rows, &title(rows, source), &ctx))?;
+    Ok(())
+}
+
+// `note.md` -> `note.html`
+pub fn default_output(source: &str, extension: &str) -> String {
+    Path::new(source).with_extension(extension).to_string_lossy().to_string()
+}
+
+// Handles `next export --to html note.md [-o out.html]`.
+pub fn run_cli(args: &[String]) -> Result<String, Box<dyn Error>> {
+    let mut format = "html".to_string();
+    let mut output = None;
+    let mut input = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--to" | "-t" => format = args.next().ok_or("--to needs a format")?.clone(),
+            "--output" | "-o" => output = Some(args.next().ok_or("--output needs a file name")?.clone()),
+            _ => input = Some(arg.clone()),
+        }
+    }
+
+    if format != "html" {
+        return Err(format!("Unsupported export format: {}", format).into());
+    }
+    let input = input.ok_or("Usage: next export --to html <file> [-o <output>]")?;
+    let output = output.unwrap_or_else(|| default_output(&input, "html"));
+
+    let rows = read_lines(&input)?;
+    export_html(&rows, Some(&input), &output)?;
+
+    Ok(format!("Exported {} to {}", input, output))
+}
+
+fn code_ansi(line: &str, lang: &str) -> String {
+    let mut out = String::new();
+    for (token, text) in highlight(line, lang) {
+        let color = match token {
+            Token::Keyword => "\x1b[31m",
+            Token::String => "\x1b[32m",
+            Token::Number => "\x1b[35m",
+            Token::Comment => "\x1b[2;3m",
+            Token::Plain => "\x1b[36m",
+        };
+        out.push_str(color);
+        out.push_str(&text.replace('\t', "    "));
+        out.push_str("\x1b[0m");
+    }
+    out
+}
+
+fn table_ansi(table: &Table, color: bool) -> Vec<String> {
+    let cells: Vec<Vec<String>> = table.rows.iter().map(|row| {
+        row.iter().map(|cell| {
+            let text = render_line(cell, Conceal::Full).0;
+            if color { text } else { strip_escapes(&text) }
+        }).collect()
+    }).collect();
+
+    let mut widths = vec![0; table.columns()];
+    for row in cells.iter() {
+        for (i, cell) in row.iter().enumerate() {
+            widths[i] = widths[i].max(visible_width(cell));
+        }
+    }
+
+    let (bar, rule, cross) = if color { ("│", "─", "┼") } else { ("|", "-", "+") };
+    let mut lines = Vec::new();
+    for (r, row) in cells.iter().enumerate() {
+        let padded: Vec<String> = row.iter().enumerate().map(|(i, cell)| {
+            let fill = " ".repeat(widths[i] - visible_width(cell));
+            match (r, color) {
+                (0, true) => format!("\x1b[1m{}\x1b[0m{}", cell, fill),
+                _ => format!("{}{}", cell, fill),
+            }
+        }).collect();
+        lines.push(format!("{}{}", table.indent, padded.join(&format!(" {} ", bar)).trim_end()));
+
+        if r == 0 {
+            let rules: Vec<String> = widths.iter().map(|w| rule.repeat(*w)).collect();
+            lines.push(format!("{}{}", table.indent, rules.join(&format!("{}{}{}", rule, cross, rule))));
+        }
+    }
+
+    lines
+}
+
+// The rendered document as text for a terminal, or as plain text without `color`.
+pub fn to_ansi(rows: &mut [Line], width: Option<usize>, color: bool) -> String {
+    parse_lines_to_markdown(rows, Conceal::Full);
+    let headings = headings(rows);
+
+    let mut out = Vec::new();
+    let mut lang = String::new();
+    let mut i = front_matter_len(rows);
+    while i < rows.len() {
+        let line = &rows[i];
+
+        if line.code {
+            if is_fence(&line.row) {
+                lang = line.row.trim_start().trim_start_matches(['`', '~']).trim().to_string();
+            } else if color {
+                out.push(code_ansi(&line.row, &lang));
+            } else {
+                out.push(format!("    {}", line.row));
+            }
+            i += 1;
+            continue;
+        }
+
+        if is_table_row(&line.row) {
+            let start = i;
+            while i < rows.len() && is_table_row(&rows[i].row) {
+                i += 1;
+            }
+            let lines: Vec<String> = rows[start..i].iter().map(|line| line.row.clone()).collect();
+            out.extend(table_ansi(&Table::parse(&lines), color));
+            continue;
+        }
+
+        let heading = headings.iter().find(|h| h.line == i);
+        if heading.is_some() && is_setext_underline(rows, i + 1) {
+            i += 1;
+        }
+
+        let text = if color { line.render.clone() } else { strip_escapes(&line.render) };
+        let indent = list_prefix(&line.row).map_or(0, |p| line.cols[p.len]);
+        match width {
+            Some(width) => {
+                let indent = hanging_indent(indent, width);
+                let plain = strip_escapes(&text);
+                for (n, (start, end)) in wrap_columns(&plain, width, indent, true).into_iter().enumerate() {
+                    let pad = if n == 0 { String::new() } else { " ".repeat(indent) };
+                    let row = slice_columns(&text, start, end - start);
+                    out.push(format!("{}{}", pad, if color { row } else { strip_escapes(&row) }.trim_end()));
+                }
+            },
+            None => out.push(text),
+        }
+
+        // Without color, underline top level headings so they still stand out
+        if let (Some(h), false) = (heading, color) {
+            if h.level <= 2 {
+                let underline = if h.level == 1 { "=" } else { "-" };
+                out.push(underline.repeat(visible_width(out.last().unwrap()).max(3)));
+            }
+        }
+        i += 1;
+    }
+
+    let mut text = out.join("\n");
+    text.push('\n');
+    text
+}
+
+// Handles `next render note.md [--width N] [--no-color]`.
+pub fn run_render_cli(args: &[String]) -> Result<String, Box<dyn Error>> {
+    let mut width = None;
+    let mut color = env::var_os("NO_COLOR").is_none();
+    let mut input = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--width" | "-w" => {
+                let value = args.next().ok_or("--width needs a number")?;
+                width = Some(value.parse::<usize>().map_err(|_| format!("Invalid width: {}", value))?.max(1));
+            },
+            "--no-color" => color = false,
+            _ => input = Some(arg.clone()),
+        }
+    }
+
+    let mut rows = match input.as_deref() {
+        None | Some("-") => io::stdin().lines().map(|line| line.map(Line::new)).collect::<Result<Vec<Line>, _>>()?,
+        Some(input) => read_lines(input)?,
+    };
+
+    Ok(to_ansi(&mut rows, width, color))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(text: &str) -> Vec<Line> {
+        text.lines().map(|line| Line::new(line.to_string())).collect()
+    }
+
+    fn html(text: &str) -> String {
+        body_html(&rows(text), &Context::new(Some("notes/a.md"), "notes/a.html"))
+    }
+
+    #[test]
+    fn repeated_headings_get_unique_anchors() {
+        let text = "# Intro\n## Setup\n# Intro\n## Set-up!";
+        assert_eq!(heading_slugs(&rows(text)), vec!["intro", "setup", "intro-1", "set-up"]);
+
+        let body = html(text);
+        assert!(body.contains("<h1 id=\"intro-1\"><a class=\"anchor\" href=\"#intro-1\">#</a>Intro</h1>"), "{}", body);
+        let toc = toc_html(&rows(text));
+        assert!(toc.contains("<a href=\"#intro\">Intro</a>") && toc.contains("<a href=\"#intro-1\">Intro</a>"), "{}", toc);
+    }
+
+    #[test]
+    fn links_to_notes_point_at_their_exported_pages() {
+        let ctx = Context::new(Some("notes/a.md"), "out/a.html");
+        let body = body_html(&rows("[b](b.md#top) [[c note#Some Part]] [u](../up/u.md)"), &ctx);
+        assert!(body.contains("<a href=\"../notes/b.html#top\">b</a>"), "{}", body);
+        assert!(body.contains("<a href=\"../notes/c%20note.html#some-part\">"), "{}", body);
+        assert!(body.contains("<a href=\"../up/u.html\">u</a>"), "{}", body);
+    }
+
+    #[test]
+    fn other_links_keep_their_targets() {
+        let ctx = Context::new(Some("notes/a.md"), "out/a.html");
+        let body = body_html(&rows("[x](https://x.org/a.md) [s](#setup) ![i](img/p.png)"), &ctx);
+        assert!(body.contains("<a href=\"https://x.org/a.md\">x</a>"), "{}", body);
+        assert!(body.contains("<a href=\"#setup\">s</a>"), "{}", body);
+        assert!(body.contains("<img src=\"../notes/img/p.png\" alt=\"i\">"), "{}", body);
+    }
+
+    #[test]
+    fn code_blocks_are_escaped() {
+        let body = html("```rust\nif a < b && s == \"<x>\" { // <tag>\n```\n```\n<div>\n```");
+        assert!(body.contains("<span class=\"kw\">if</span> a &lt; b &amp;&amp; s == <span class=\"str\">&quot;&lt;x&gt;&quot;</span> { <span class=\"com\">// &lt;tag&gt;</span>"), "{}", body);
+        assert!(body.contains("&lt;div&gt;") && !body.contains("<div>"), "{}", body);
+    }
+
+    #[test]
+    fn to_ansi_wraps_list_items_under_their_text() {
+        assert_eq!(to_ansi(&mut rows("- one two three four five six"), Some(14), false), "• one two\n  three four\n  five six\n");
+    }
+
+    #[test]
+    fn to_ansi_drops_the_indent_when_it_leaves_no_room() {
+        let text = to_ansi(&mut rows("        - item one two three four five six seven"), Some(12), false);
+        assert!(text.lines().all(|line| visible_width(line) <= 12), "{}", text);
+        assert!(text.contains("\nitem one\n"), "{}", text);
+    }
+
+    #[test]
+    fn to_ansi_without_color_has_no_escapes() {
+        let text = to_ansi(&mut rows("# Title\n\nsome **bold** and `code`"), None, false);
+        assert_eq!(text, "Title\n=====\n\nsome bold and code\n");
+        assert!(to_ansi(&mut rows("some **bold**"), None, true).contains("\x1b[1mbold"));
+
+        let file = env::temp_dir().join(format!("next-render-{}.md", std::process::id()));
+        fs::write(&file, "some **bold**").unwrap();
+        let args = ["--no-color".to_string(), file.to_string_lossy().to_string()];
+        assert_eq!(run_render_cli(&args).unwrap(), "some bold\n");
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn to_ansi_lines_up_tables() {
+        let text = to_ansi(&mut rows("| a | bb |\n|---|---:|\n| 1 | 22 |"), None, false);
+        assert_eq!(text, "a | bb\n--+---\n1 | 22\n");
+    }
+
+    #[test]
+    fn lines_starting_with_a_tag_keep_the_tag() {
+        assert_eq!(to_ansi(&mut rows("#tag line"), None, false), "#tag line\n");
+        let body = html("#tag line");
+        assert!(body.starts_with("<p>"), "{}", body);
+        assert!(body.contains("#tag"), "{}", body);
+        assert!(html("# Title").starts_with("<h1"));
+    }
+}
@@ -0,0 +1,252 @@
+// A popup that fuzzy-matches the files of the workspace as you type.
+use crate::list::truncate;
+
+pub struct Entry {
+    pub path: String,
+    // First heading or front matter title of a note.
+    pub title: Option<String>,
+}
+
+struct Match {
+    entry: usize,
+    score: i64,
+    // Matched character positions in the path and in the title.
+    path_hits: Vec<usize>,
+    title_hits: Vec<usize>,
+}
+
+pub struct Finder {
+    pub query: String,
+    entries: Vec<Entry>,
+    matches: Vec<Match>,
+    selected: usize,
+    offset: usize,
+}
+
+// Scores `text` against `pattern` when every character of the pattern appears
+// in order. Consecutive characters and word starts score higher, gaps cost.
+pub fn fuzzy_match(pattern: &str, text: &str) -> Option<(i64, Vec<usize>)> {
+    let pattern: Vec<char> = pattern.chars().filter(|c| !c.is_whitespace()).flat_map(|c| c.to_lowercase()).collect();
+    let chars: Vec<char> = text.chars().collect();
+    let lower: Vec<char> = chars.iter().map(|c| c.to_lowercase().next().unwrap_or(*c)).collect();
+    if pattern.is_empty() {
+        return Some((0, Vec::new()));
+    }
+
+    // Find where the first full match ends, then walk back from there for the
+    // tightest run of matching characters.
+    let mut p = 0;
+    let mut end = None;
+    for (i, c) in lower.iter().enumerate() {
+        if *c == pattern[p] {
+            p += 1;
+            if p == pattern.len() {
+                end = Some(i);
+                break;
+            }
+        }
+    }
+    let end = end?;
+
+    let mut hits = Vec::new();
+    let mut p = pattern.len();
+    for i in (0..=end).rev() {
+        if p > 0 && lower[i] == pattern[p - 1] {
+            p -= 1;
+            hits.push(i);
+        }
+    }
+    hits.reverse();
+
+    let mut score = 0;
+    for (n, &i) in hits.iter().enumerate() {
+        score += 16;
+        if i == 0 || matches!(chars[i - 1], '/' | '_' | '-' | ' ' | '.') {
+            score += 24;
+        }
+        if n > 0 {
+            let gap = (i - hits[n - 1] - 1) as i64;
+            score += if gap == 0 { 16 } else { -gap.min(12) };
+        }
+    }
+    score -= (chars.len() as i64) / 8;
+
+    Some((score, hits))
+}
+
+impl Finder {
+    pub fn new(entries: Vec<Entry>) -> Finder {
+        let mut finder = Finder { query: String::new(), entries, matches: Vec::new(), selected: 0, offset: 0 };
+        finder.update();
+        finder
+    }
+
+    // Re-ranks the entries against the query.
+    pub fn update(&mut self) {
+        self.matches = self.entries.iter().enumerate().filter_map(|(i, entry)| {
+            let path = fuzzy_match(&self.query, &entry.path);
+            let title = entry.title.as_ref().and_then(|title| fuzzy_match(&self.query, title));
+            match (path, title) {
+                (Some((score, path_hits)), Some((title_score, _))) if score >= title_score => Some(Match { entry: i, score, path_hits, title_hits: Vec::new() }),
+                (_, Some((score, title_hits))) => Some(Match { entry: i, score, path_hits: Vec::new(), title_hits }),
+                (Some((score, path_hits)), None) => Some(Match { entry: i, score, path_hits, title_hits: Vec::new() }),
+                (None, None) => None,
+            }
+        }).collect();
+
+        let entries = &self.entries;
+        self.matches.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| entries[a.entry].path.cmp(&entries[b.entry].path)));
+        self.selected = 0;
+        self.offset = 0;
+    }
+
+    pub fn current(&self) -> Option<&Entry> {
+        self.matches.get(self.selected).map(|m| &self.entries[m.entry])
+    }
+
+    pub fn next(&mut self) {
+        if self.selected + 1 < self.matches.len() {
+            self.selected += 1;
+        }
+    }
+
+    pub fn prev(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    // The popup as `height` lines of `width` columns: a frame holding the
+    // query line and the best matches.
+    pub fn render(&mut self, height: u16, width: u16) -> Vec<String> {
+        let inner = (width as usize).saturating_sub(2);
+        let rows = (height as usize).saturating_sub(4);
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        }
+        if rows > 0 && self.selected >= self.offset + rows {
+            self.offset = self.selected + 1 - rows;
+        }
+
+        let mut lines = Vec::new();
+        let title = format!(" Find {}/{} ", self.matches.len(), self.entries.len());
+        lines.push(format!("┌{}{}┐", truncate(&title, inner as u16), "─".repeat(inner.saturating_sub(title.chars().count()))));
+        let prompt = truncate(&format!("> {}", self.query), inner as u16);
+        lines.push(format!("│{}{}│", prompt, " ".repeat(inner - prompt.chars().count())));
+        lines.push(format!("├{}┤", "─".repeat(inner)));
+
+        for row in 0..rows {
+            let text = match self.matches.get(self.offset + row) {
+                Some(m) => {
+                    let entry = &self.entries[m.entry];
+                    let mut cells = highlight(&entry.path, &m.path_hits);
+                    if let Some(title) = &entry.title {
+                        cells.push((' ', false));
+                        cells.push((' ', false));
+                        cells.extend(highlight(title, &m.title_hits));
+                    }
+                    paint(&cells, inner, self.offset + row == self.selected)
+                },
+                None => " ".repeat(inner),
+            };
+            lines.push(format!("│{}│", text));
+        }
+        lines.push(format!("└{}┘", "─".repeat(inner)));
+
+        lines
+    }
+}
+
+fn highlight(text: &str, hits: &[usize]) -> Vec<(char, bool)> {
+    text.chars().enumerate().map(|(i, c)| (c, hits.contains(&i))).collect()
+}
+
+// Draws characters in `width` columns, matched ones in bold yellow.
+fn paint(cells: &[(char, bool)], width: usize, selected: bool) -> String {
+    let base = if selected { "\x1b[0;7m" } else { "\x1b[0m" };
+    let mut out = String::from(base);
+    if width > 0 {
+        out.push(' ');
+    }
+    for (c, hit) in cells.iter().take(width.saturating_sub(1)) {
+        if *hit {
+            out.push_str("\x1b[1;33m");
+            out.push(*c);
+            out.push_str(base);
+        } else {
+            out.push(*c);
+        }
+    }
+    out.push_str(&" ".repeat(width.saturating_sub(1 + cells.len())));
+    out.push_str("\x1b[0m");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::screen::visible_width;
+
+    fn entry(path: &str, title: Option<&str>) -> Entry {
+        Entry { path: path.to_string(), title: title.map(String::from) }
+    }
+
+    fn ranked(finder: &mut Finder, query: &str) -> Vec<String> {
+        finder.query = query.to_string();
+        finder.update();
+        finder.matches.iter().map(|m| finder.entries[m.entry].path.clone()).collect()
+    }
+
+    #[test]
+    fn fuzzy_match_needs_every_character_in_order() {
+        assert_eq!(fuzzy_match("nts", "notes.md").map(|(_, hits)| hits), Some(vec![0, 2, 4]));
+        assert_eq!(fuzzy_match("N T", "notes.md").map(|(_, hits)| hits), Some(vec![0, 2]));
+        assert_eq!(fuzzy_match("stn", "notes.md"), None);
+        assert_eq!(fuzzy_match("", "notes.md"), Some((0, Vec::new())));
+    }
+
+    #[test]
+    fn fuzzy_match_picks_the_tightest_run() {
+        // The last `p` and `l` are nearer each other than the first ones
+        assert_eq!(fuzzy_match("pl", "p/x/plan.md").map(|(_, hits)| hits), Some(vec![4, 5]));
+    }
+
+    #[test]
+    fn runs_and_word_starts_score_higher() {
+        let score = |pattern, text| fuzzy_match(pattern, text).unwrap().0;
+        assert!(score("plan", "plan.md") > score("plan", "pxlxaxn.md"));
+        assert!(score("rd", "docs/read.md") > score("rd", "docs/bird.md"));
+        assert!(score("a", "a.md") > score("a", "a/very/long/path/to/a/note.md"));
+    }
+
+    #[test]
+    fn matches_rank_by_score_then_path() {
+        let mut finder = Finder::new(vec![
+            entry("z/meeting.md", None),
+            entry("archive/my-old-notes.md", None),
+            entry("m.md", Some("Meeting notes")),
+            entry("b/meeting.md", None),
+        ]);
+        // The title of `m.md` scores the same as the paths, so path order decides
+        assert_eq!(ranked(&mut finder, "meet"), vec!["b/meeting.md", "m.md", "z/meeting.md"]);
+        assert_eq!(finder.current().map(|e| e.path.as_str()), Some("b/meeting.md"));
+        assert_eq!(ranked(&mut finder, "xyz"), Vec::<String>::new());
+        assert!(finder.current().is_none());
+    }
+
+    #[test]
+    fn titles_match_when_they_beat_the_path() {
+        let mut finder = Finder::new(vec![entry("2026-03-05.md", Some("Launch plan"))]);
+        assert_eq!(ranked(&mut finder, "launch"), vec!["2026-03-05.md"]);
+        assert!(finder.matches[0].path_hits.is_empty());
+        assert_eq!(finder.matches[0].title_hits, vec![0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn render_keeps_its_frame_in_tiny_popups() {
+        let mut finder = Finder::new(vec![entry("notes/plan.md", Some("Plan"))]);
+        for (height, width) in [(0, 0), (3, 1), (5, 2)] {
+            let lines = finder.render(height, width);
+            assert_eq!(lines.len(), 4 + (height as usize).saturating_sub(4));
+            assert!(lines.iter().all(|line| visible_width(line) == width.max(2) as usize), "{:?}", lines);
+        }
+    }
+}
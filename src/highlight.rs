@@ -0,0 +1,103 @@
+// A small keyword based highlighter for fenced code blocks. It only knows
+// enough about common languages to tell comments, strings, numbers and
+// keywords apart.
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Token {
+    Plain,
+    Keyword,
+    String,
+    Number,
+    Comment,
+}
+
+static KEYWORDS: [&str; 58] = [
+    "as", "async", "await", "break", "case", "catch", "class", "const", "continue", "def",
+    "default", "do", "elif", "else", "enum", "export", "extends", "false", "fn", "for",
+    "from", "func", "function", "if", "impl", "import", "in", "interface", "let", "loop",
+    "match", "mod", "mut", "new", "nil", "None", "null", "package", "pub", "return",
+    "self", "static", "struct", "super", "switch", "this", "throw", "trait", "true", "try",
+    "type", "use", "var", "void", "where", "while", "with", "yield",
+];
+
+fn line_comment(lang: &str) -> &'static [&'static str] {
+    match lang {
+        "python" | "py" | "sh" | "bash" | "zsh" | "ruby" | "rb" | "yaml" | "yml" | "toml" => &["#"],
+        "sql" | "lua" | "haskell" | "hs" => &["--"],
+        "" => &["//", "#"],
+        _ => &["//"],
+    }
+}
+
+pub fn highlight<'a>(line: &'a str, lang: &str) -> Vec<(Token, &'a str)> {
+    let comments = line_comment(lang);
+    let mut tokens: Vec<(Token, usize, usize)> = Vec::new();
+    let mut pos = 0;
+
+    while pos < line.len() {
+        let rest = &line[pos..];
+        if comments.iter().any(|c| rest.starts_with(c)) {
+            tokens.push((Token::Comment, pos, line.len()));
+            break;
+        }
+
+        let c = rest.chars().next().unwrap();
+        let (token, len) = if c == '"' || c == '\'' || c == '`' {
+            let mut escaped = false;
+            let end = rest[1..].char_indices().find(|(_, ch)| {
+                let done = *ch == c && !escaped;
+                escaped = *ch == '\\' && !escaped;
+                done
+            });
+            (Token::String, end.map_or(rest.len(), |(i, _)| i + 2))
+        } else if c.is_ascii_digit() {
+            (Token::Number, rest.find(|ch: char| !ch.is_ascii_alphanumeric() && ch != '.' && ch != '_').unwrap_or(rest.len()))
+        } else if c.is_alphabetic() || c == '_' {
+            let len = rest.find(|ch: char| !ch.is_alphanumeric() && ch != '_').unwrap_or(rest.len());
+            (if KEYWORDS.contains(&&rest[..len]) { Token::Keyword } else { Token::Plain }, len)
+        } else {
+            (Token::Plain, c.len_utf8())
+        };
+
+        match tokens.last_mut() {
+            Some((Token::Plain, _, end)) if token == Token::Plain && *end == pos => *end = pos + len,
+            _ => tokens.push((token, pos, pos + len)),
+        }
+        pos += len;
+    }
+
+    tokens.into_iter().map(|(token, start, end)| (token, &line[start..end])).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn highlight_splits_keywords_strings_numbers_and_comments() {
+        let tokens = highlight("let s = \"a \\\" b\"; x = 0x1f; // done", "rust");
+        assert_eq!(tokens, vec![
+            (Token::Keyword, "let"),
+            (Token::Plain, " s = "),
+            (Token::String, "\"a \\\" b\""),
+            (Token::Plain, "; x = "),
+            (Token::Number, "0x1f"),
+            (Token::Plain, "; "),
+            (Token::Comment, "// done"),
+        ]);
+    }
+
+    #[test]
+    fn comment_markers_follow_the_language() {
+        assert_eq!(highlight("x # y", "python").last(), Some(&(Token::Comment, "# y")));
+        assert_eq!(highlight("x -- y", "sql").last(), Some(&(Token::Comment, "-- y")));
+        assert_eq!(highlight("x # y", "rust"), vec![(Token::Plain, "x # y")]);
+        assert_eq!(highlight("# y", ""), vec![(Token::Comment, "# y")]);
+    }
+
+    #[test]
+    fn unterminated_strings_run_to_the_end_of_the_line() {
+        assert_eq!(highlight("'open", "js"), vec![(Token::String, "'open")]);
+        assert_eq!(highlight("", "js"), vec![]);
+    }
+}
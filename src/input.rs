@@ -8,6 +8,8 @@ pub enum Keys {
     Char(char),
     Esc,
     Enter,
+    Tab,
+    BackTab,
     Up,
     Down,
     Left,
@@ -20,7 +22,13 @@ pub enum Keys {
     BackSpace,
     None,
 
-    SaveFile
+    SaveFile,
+    JumpBack,
+    Find,
+    SplitVertical,
+    SplitHorizontal,
+    // The terminal's new columns and rows.
+    Resize(u16, u16),
 }
 
 pub struct Input {
@@ -28,7 +36,11 @@ pub struct Input {
 
 impl Input {
     pub fn handle_input(&mut self) -> Keys {
-        if let Event::Key(KeyEvent { code, modifiers, kind, state: _ }) = read().expect("Failed to read key event") {
+        let event = read().expect("Failed to read key event");
+        if let Event::Resize(cols, rows) = event {
+            return Keys::Resize(cols, rows);
+        }
+        if let Event::Key(KeyEvent { code, modifiers, kind, state: _ }) = event {
             if kind == KeyEventKind::Press {
                 if modifiers == KeyModifiers::CONTROL {
                     if let KeyCode::Char(c) = code {
@@ -41,6 +53,18 @@ impl Input {
                             'w' => {
                                 return Keys::SaveFile;
                             }
+                            'o' => {
+                                return Keys::JumpBack;
+                            }
+                            'p' => {
+                                return Keys::Find;
+                            }
+                            'v' => {
+                                return Keys::SplitVertical;
+                            }
+                            's' => {
+                                return Keys::SplitHorizontal;
+                            }
                             _ => ()
                         }
                     }
@@ -52,6 +76,8 @@ impl Input {
                             return Keys::Char(c);
                         }
                         KeyCode::Enter => Keys::Enter,
+                        KeyCode::Tab => Keys::Tab,
+                        KeyCode::BackTab => Keys::BackTab,
                         KeyCode::Esc => Keys::Esc,
                         KeyCode::Up => Keys::Up,
                         KeyCode::Down => Keys::Down,
@@ -68,7 +94,7 @@ impl Input {
                 }
             }
         }
-        return Keys::None;
+        Keys::None
     }       
 }
 
@@ -2,3 +2,12 @@ pub mod input;
 pub mod screen;
 pub mod terminal;
 pub mod markdown;
+pub mod table;
+pub mod date;
+pub mod list;
+pub mod highlight;
+pub mod export;
+pub mod notes;
+pub mod workspace;
+pub mod finder;
+pub mod window;
@@ -0,0 +1,116 @@
+// A navigable list of locations shown in a panel below the text area.
+#[derive(Clone)]
+pub struct ListItem {
+    pub text: String,
+    pub line: usize,
+    pub col: usize,
+    // Set when the location is in another note.
+    pub file: Option<String>,
+}
+
+// What a list holds, which decides what its keys do.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ListKind {
+    Tasks,
+    Outline,
+    Search,
+    Backlinks,
+    Rename,
+    Tags,
+    Query,
+    Journal,
+    Quickfix,
+}
+
+pub struct ListView {
+    pub kind: ListKind,
+    pub title: String,
+    pub items: Vec<ListItem>,
+    pub selected: usize,
+    offset: usize,
+}
+
+impl ListView {
+    pub fn new(kind: ListKind, title: String, items: Vec<ListItem>) -> ListView {
+        ListView { kind, title, items, selected: 0, offset: 0 }
+    }
+
+    pub fn current(&self) -> Option<&ListItem> {
+        self.items.get(self.selected)
+    }
+
+    pub fn next(&mut self) {
+        if self.selected + 1 < self.items.len() {
+            self.selected += 1;
+        }
+    }
+
+    pub fn prev(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    // Rows needed to draw the list, title included, capped at `max`.
+    pub fn height(&self, max: u16) -> u16 {
+        (self.items.len().max(1) as u16 + 1).min(max)
+    }
+
+    pub fn render(&mut self, height: u16, width: u16) -> Vec<String> {
+        let rows = height.saturating_sub(1) as usize;
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        }
+        if rows > 0 && self.selected >= self.offset + rows {
+            self.offset = self.selected + 1 - rows;
+        }
+
+        let mut lines = Vec::new();
+        let title = format!(" {} ({})", self.title, self.items.len());
+        lines.push(format!("\x1b[7m{}{}\x1b[m", truncate(&title, width), " ".repeat((width as usize).saturating_sub(title.chars().count()))));
+
+        if self.items.is_empty() {
+            lines.push(" (empty)".to_string());
+        }
+
+        for (i, item) in self.items.iter().enumerate().skip(self.offset).take(rows) {
+            let text = truncate(&format!(" {}", item.text), width);
+            if i == self.selected {
+                lines.push(format!("\x1b[1;7m{}\x1b[m", text));
+            } else {
+                lines.push(text);
+            }
+        }
+
+        lines
+    }
+}
+
+pub fn truncate(s: &str, width: u16) -> String {
+    s.chars().take(width as usize).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(items: &[&str]) -> ListView {
+        let items = items.iter().map(|text| ListItem { text: text.to_string(), line: 0, col: 0, file: None }).collect();
+        ListView::new(ListKind::Tasks, "Tasks".to_string(), items)
+    }
+
+    #[test]
+    fn render_cuts_lines_to_narrow_panels() {
+        let mut view = list(&["one", "two", "three"]);
+        assert_eq!(view.render(2, 3), vec!["\x1b[7m Ta\x1b[m", "\x1b[1;7m on\x1b[m"]);
+        assert_eq!(view.render(0, 0), vec!["\x1b[7m\x1b[m"]);
+    }
+
+    #[test]
+    fn render_scrolls_to_the_selection() {
+        let mut view = list(&["one", "two", "three"]);
+        view.next();
+        view.next();
+        assert_eq!(view.render(2, 20)[1], "\x1b[1;7m three\x1b[m");
+        assert_eq!(view.height(2), 2);
+        assert_eq!(view.height(10), 4);
+    }
+}
@@ -1,12 +1,48 @@
 use std::env;
+use std::io::{self, ErrorKind, Write};
+use std::process::exit;
+use next::export;
 use next::terminal::Terminal;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
+    if args.len() > 1 && args[1] == "export" {
+        match export::run_cli(&args[2..]) {
+            Ok(message) => println!("{}", message),
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(1);
+            }
+        }
+        return;
+    }
+
+    if args.len() > 1 && args[1] == "render" {
+        match export::run_render_cli(&args[2..]) {
+            Ok(text) => {
+                // A reader like `head` may stop early, which is not an error
+                if let Err(e) = io::stdout().lock().write_all(text.as_bytes()) {
+                    if e.kind() != ErrorKind::BrokenPipe {
+                        eprintln!("{}", e);
+                        exit(1);
+                    }
+                }
+            },
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(1);
+            }
+        }
+        return;
+    }
+
     let mut terminal = Terminal::new();
 
-    if args.len() > 1 {
+    if args.len() > 1 && args[1] == "today" {
+        terminal.open_empty_editor();
+        terminal.open_journal(0);
+    } else if args.len() > 1 {
         if let Err(e) = terminal.open_editor(args[1].clone()) {
             eprintln!("Error reading file: {}\n {}", args[1], e);
         }
@@ -1,47 +1,968 @@
+use crate::date::Date;
 use crate::terminal::Line;
 use regex::Regex;
+use std::sync::OnceLock;
 
-static DEFAULT_RULES: [(&str, &str); 13] = [
-    (r#"^#{6}\s?([^\n]+)"#, "\x1b[1;34m$1\x1b[0m"), // H6
-    (r#"^#{5}\s?([^\n]+)"#, "\x1b[1;35m$1\x1b[0m"), // H5
-    (r#"^#{4}\s?([^\n]+)"#, "\x1b[1;36m$1\x1b[0m"), // H4
-    (r#"^#{3}\s?([^\n]+)"#, "\x1b[1;32m$1\x1b[0m"), // H3
-    (r#"^#{2}\s?([^\n]+)"#, "\x1b[1;33m$1\x1b[0m"), // H2
-    (r#"^#{1}\s?([^\n]+)"#, "\x1b[1;31m$1\x1b[0m"), // H1
-    (r#"\*\*\s?([^\n]+)\*\*"#, "\x1b[1m$1\x1b[0m"), // Bold
-    (r#"\_\_\s?([^\n]+)\_\_"#, "\x1b[1m$1\x1b[0m"), // Bold
-    (r#"^\*\s?([^\n]+)$"#, "• $1\x1b[0m"),          // Points
-    (r#"\*\s?([^\n]+)\*"#, "\x1b[3m$1\x1b[0m"),     // Italics
-    (r#"\_\s?([^\n]+)\_"#, "\x1b[3m$1\x1b[0m"),     // Italics
-    (r#"\!\[([^\]]+)\]\((\S+)\)"#, "$1(\x1b[4;34m$2\x1b[0m)"), // Image links
-    (r#"\[([^\n]+)\]\(([^\n]+)\)"#, "$1(\x1b[4;34m$2\x1b[0m)"),// Links
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Conceal {
+    Raw,
+    Partial,
+    Full,
+}
+
+impl Conceal {
+    pub fn from_level(level: &str) -> Option<Conceal> {
+        match level {
+            "0" => Some(Conceal::Raw),
+            "1" => Some(Conceal::Partial),
+            "2" => Some(Conceal::Full),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub struct Style {
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub strike: bool,
+    pub dim: bool,
+    pub color: Option<u8>,
+}
+
+const PLAIN: Style = Style { bold: false, italic: false, underline: false, strike: false, dim: false, color: None };
+const BOLD: Style = Style { bold: true, ..PLAIN };
+const ITALIC: Style = Style { italic: true, ..PLAIN };
+const STRIKE: Style = Style { strike: true, ..PLAIN };
+const DIM: Style = Style { dim: true, ..PLAIN };
+const CODE: Style = Style { color: Some(36), ..PLAIN };
+const LINK: Style = Style { underline: true, color: Some(34), ..PLAIN };
+const TAG: Style = Style { color: Some(35), ..PLAIN };
+
+const fn heading(color: u8) -> Style {
+    Style { bold: true, color: Some(color), ..PLAIN }
+}
+
+impl Style {
+    // Layers `other` on top of `self`.
+    pub fn with(&self, other: Style) -> Style {
+        Style {
+            bold: self.bold || other.bold,
+            italic: self.italic || other.italic,
+            underline: self.underline || other.underline,
+            strike: self.strike || other.strike,
+            dim: self.dim || other.dim,
+            color: other.color.or(self.color),
+        }
+    }
+
+    pub fn sgr(&self) -> String {
+        let mut codes = Vec::new();
+        if self.bold { codes.push("1".to_string()); }
+        if self.dim { codes.push("2".to_string()); }
+        if self.italic { codes.push("3".to_string()); }
+        if self.underline { codes.push("4".to_string()); }
+        if self.strike { codes.push("9".to_string()); }
+        if let Some(color) = self.color { codes.push(color.to_string()); }
+
+        if codes.is_empty() { String::new() } else { format!("\x1b[{}m", codes.join(";")) }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Part {
+    Text(Style),
+    Marker,
+    Url,
+}
+
+// A run of the source line and what it means.
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub part: Part,
+    // The HTML element the content stands for, e.g. `strong` or `a`.
+    pub tag: Option<&'static str>,
+    // Byte range of the target of a link or image.
+    pub url: Option<(usize, usize)>,
+}
+
+pub struct ParsedLine {
+    pub spans: Vec<Span>,
+    // Byte range of the block marker, e.g. `## ` or `- [ ] `.
+    pub marker: Option<(usize, usize)>,
+    // Shown instead of the block marker when markup is concealed.
+    pub glyph: Option<&'static str>,
+}
+
+// Block rules are anchored at the start of the line. Group 1 is the marker,
+// and the style applies to everything after it.
+static BLOCK_RULES: [(&str, Style, Option<&str>); 10] = [
+    (r#"^(#{6}(?:\s|$))"#, heading(34), None), // H6
+    (r#"^(#{5}(?:\s|$))"#, heading(35), None), // H5
+    (r#"^(#{4}(?:\s|$))"#, heading(36), None), // H4
+    (r#"^(#{3}(?:\s|$))"#, heading(32), None), // H3
+    (r#"^(#{2}(?:\s|$))"#, heading(33), None), // H2
+    (r#"^(#{1}(?:\s|$))"#, heading(31), None), // H1
+    (r#"^\s*([-*+]\s\[\s\]\s?)"#, PLAIN, Some("☐ ")),    // Open task
+    (r#"^\s*([-*+]\s\[[xX]\]\s?)"#, STRIKE, Some("☑ ")), // Done task
+    (r#"^\s*([-*+]\s)"#, PLAIN, Some("• ")),             // Points
+    (r#"^\s*(>\s?)"#, DIM, Some("│ ")),                  // Quote
+];
+
+// Every capture group of an inline rule is either markup or content. The tag
+// names the element the content becomes when exported.
+const TAG_RULE: &str = r#"(^|\s)(#[A-Za-z][\w/-]*)"#;
+
+static INLINE_RULES: [(&str, &[Part], Option<&str>); 12] = [
+    (r#"(`)([^`]+)(`)"#, &[Part::Marker, Part::Text(CODE), Part::Marker], Some("code")),  // Code
+    (r#"(\[\[)([^\]|]+)(\|)([^\]]+)(\]\])"#, &[Part::Marker, Part::Url, Part::Marker, Part::Text(LINK), Part::Marker], Some("wiki")), // Wiki links with alias
+    (r#"(\[\[)([^\]]+)(\]\])"#, &[Part::Marker, Part::Text(LINK), Part::Marker], Some("wiki")), // Wiki links
+    (r#"(!\[)([^\]]+)(\]\()(\S+?)(\))"#, &[Part::Marker, Part::Text(LINK), Part::Marker, Part::Url, Part::Marker], Some("img")), // Image links
+    (r#"(\[)([^\]]+)(\]\()([^)\s]+)(\))"#, &[Part::Marker, Part::Text(LINK), Part::Marker, Part::Url, Part::Marker], Some("a")), // Links
+    (TAG_RULE, &[Part::Text(PLAIN), Part::Text(TAG)], Some("tag")),                                // Tags
+    (r#"(\*\*)([^*]+)(\*\*)"#, &[Part::Marker, Part::Text(BOLD), Part::Marker], Some("strong")),   // Bold
+    (r#"(__)([^_]+)(__)"#, &[Part::Marker, Part::Text(BOLD), Part::Marker], Some("strong")),       // Bold
+    (r#"(~~)([^~]+)(~~)"#, &[Part::Marker, Part::Text(STRIKE), Part::Marker], Some("del")),        // Strikethrough
+    (r#"(\*)([^*\s][^*]*)(\*)"#, &[Part::Marker, Part::Text(ITALIC), Part::Marker], Some("em")),    // Italics
+    (r#"(_)([^_\s][^_]*)(_)"#, &[Part::Marker, Part::Text(ITALIC), Part::Marker], Some("em")),      // Italics
+    (r#"(\\)([\\`*_\[\]#|])"#, &[Part::Marker, Part::Text(PLAIN)], None),                        // Escapes
 ];
 
-const BOLD: &str = "\x1b[1m"; 
-const UNDERLINE: &str = "\x1b[4m";
-const ITALIC: &str = "\x1b[3m";
+struct Rules {
+    block: Vec<(Regex, Style, Option<&'static str>)>,
+    inline: Vec<(Regex, &'static [Part], Option<&'static str>)>,
+}
+
+fn rules() -> &'static Rules {
+    static RULES: OnceLock<Rules> = OnceLock::new();
+    RULES.get_or_init(|| Rules {
+        block: BLOCK_RULES.iter().map(|(p, style, glyph)| (Regex::new(p).unwrap(), *style, *glyph)).collect(),
+        inline: INLINE_RULES.iter().map(|(p, parts, tag)| (Regex::new(p).unwrap(), *parts, *tag)).collect(),
+    })
+}
+
+// Compiles `pattern` the first time it is needed and keeps it in `cell`.
+pub fn regex(cell: &'static OnceLock<Regex>, pattern: &str) -> &'static Regex {
+    cell.get_or_init(|| Regex::new(pattern).unwrap())
+}
+
+pub fn parse_line(row: &str) -> ParsedLine {
+    let rules = rules();
+    let mut base = PLAIN;
+    let mut glyph = None;
+    let mut marker = None;
+
+    for (re, style, g) in rules.block.iter() {
+        if let Some(caps) = re.captures(row) {
+            let m = caps.get(1).unwrap();
+            marker = Some((m.start(), m.end()));
+            base = *style;
+            glyph = *g;
+            break;
+        }
+    }
+
+    // Spans claimed by the block marker or an earlier inline rule.
+    let mut claimed: Vec<Span> = Vec::new();
+    if let Some((start, end)) = marker {
+        claimed.push(Span { start, end, part: Part::Marker, tag: None, url: None });
+    }
+
+    for (re, parts, tag) in rules.inline.iter() {
+        let mut pos = 0;
+        while let Some(caps) = re.captures_at(row, pos) {
+            let m = caps.get(0).unwrap();
+            if claimed.iter().any(|span| m.start() < span.end && span.start < m.end()) {
+                pos = m.start() + row[m.start()..].chars().next().map_or(1, |c| c.len_utf8());
+                continue;
+            }
+
+            let url = parts.iter().position(|part| *part == Part::Url)
+                .and_then(|i| caps.get(i + 1))
+                .map(|g| (g.start(), g.end()));
+            for (i, part) in parts.iter().enumerate() {
+                if let Some(g) = caps.get(i + 1) {
+                    let content = matches!(part, Part::Text(_));
+                    claimed.push(Span {
+                        start: g.start(),
+                        end: g.end(),
+                        part: *part,
+                        tag: if content { *tag } else { None },
+                        url: if content { url } else { None },
+                    });
+                }
+            }
+            pos = m.end();
+        }
+    }
+    claimed.sort_by_key(|span| span.start);
+
+    let mut spans = Vec::new();
+    let mut pos = 0;
+    for mut span in claimed {
+        if span.start > pos {
+            spans.push(Span { start: pos, end: span.start, part: Part::Text(base), tag: None, url: None });
+        }
+        if let Part::Text(style) = span.part {
+            span.part = Part::Text(base.with(style));
+        }
+        pos = span.end;
+        spans.push(span);
+    }
+    if pos < row.len() {
+        spans.push(Span { start: pos, end: row.len(), part: Part::Text(base), tag: None, url: None });
+    }
+
+    ParsedLine { spans, marker, glyph }
+}
+
+// Renders `row` for the screen, returning the text and the display column
+// of every byte of `row` (plus one past the end).
+pub fn render_line(row: &str, conceal: Conceal) -> (String, Vec<usize>) {
+    if conceal == Conceal::Raw {
+        return emit(row, &plain_line(row, PLAIN), conceal);
+    }
+    emit(row, &parse_line(row), conceal)
+}
+
+// Lines of fenced code blocks are shown verbatim.
+pub fn render_code_line(row: &str, conceal: Conceal) -> (String, Vec<usize>) {
+    let style = if conceal == Conceal::Raw { PLAIN } else { CODE };
+    emit(row, &plain_line(row, style), conceal)
+}
+
+// Front matter is metadata and is shown dimmed.
+pub fn render_meta_line(row: &str, conceal: Conceal) -> (String, Vec<usize>) {
+    let style = if conceal == Conceal::Raw { PLAIN } else { DIM };
+    emit(row, &plain_line(row, style), conceal)
+}
+
+fn plain_line(row: &str, style: Style) -> ParsedLine {
+    let span = Span { start: 0, end: row.len(), part: Part::Text(style), tag: None, url: None };
+    ParsedLine { spans: vec![span], marker: None, glyph: None }
+}
+
+fn emit(row: &str, parsed: &ParsedLine, conceal: Conceal) -> (String, Vec<usize>) {
+    let numtabs = 4;
+    let mut render = String::new();
+    let mut cols = vec![0; row.len() + 1];
+    let mut col = 0;
+    let mut current = PLAIN;
+
+    for span in parsed.spans.iter() {
+        let (style, hidden) = match (span.part, conceal) {
+            (Part::Text(style), _) => (style, false),
+            (_, Conceal::Partial) => (DIM, false),
+            _ => (PLAIN, true),
+        };
 
+        if let Some(glyph) = parsed.glyph.filter(|_| hidden && parsed.marker == Some((span.start, span.end))) {
+            if current != PLAIN {
+                render.push_str("\x1b[0m");
+                current = PLAIN;
+            }
+            render.push_str(glyph);
+            col += glyph.chars().count();
+        }
+
+        for (offset, c) in row[span.start..span.end].char_indices() {
+            let at = span.start + offset;
+            cols[at..at + c.len_utf8()].fill(col);
+            if hidden {
+                continue;
+            }
+
+            if style != current {
+                if current != PLAIN {
+                    render.push_str("\x1b[0m");
+                }
+                render.push_str(&style.sgr());
+                current = style;
+            }
+            if c == '\t' {
+                render.push_str(&" ".repeat(numtabs));
+                col += numtabs;
+            } else {
+                render.push(c);
+                col += 1;
+            }
+        }
+    }
+
+    if current != PLAIN {
+        render.push_str("\x1b[0m");
+    }
+    cols[row.len()] = col;
+
+    (render, cols)
+}
+
+pub fn parse_lines_to_markdown(text: &mut [Line], conceal: Conceal) {
+    let code = code_lines(text);
+    let meta = front_matter_len(text);
+    for (i, (line, code)) in text.iter_mut().zip(code).enumerate() {
+        let (render, cols) = if i < meta {
+            render_meta_line(&line.row, conceal)
+        } else if code {
+            render_code_line(&line.row, conceal)
+        } else {
+            render_line(&line.row, conceal)
+        };
+        line.render = render;
+        line.cols = cols;
+        line.code = code;
+        line.meta = i < meta;
+    }
+}
+
+// Lines taken by a `---` front matter block at the top, delimiters included.
+fn front_matter_span<'a>(mut lines: impl Iterator<Item = &'a str>) -> usize {
+    if lines.next().map(str::trim_end) != Some("---") {
+        return 0;
+    }
+    lines.position(|line| matches!(line.trim_end(), "---" | "...")).map_or(0, |i| i + 2)
+}
 
-pub fn parse_lines_to_markdown(text:&mut Vec<Line>) {
-    for i in 0..text.len() {
-        let mut parsed_line = text[i].row.clone();
+pub fn front_matter_len(rows: &[Line]) -> usize {
+    front_matter_span(rows.iter().map(|line| line.row.as_str()))
+}
+
+fn unquote(value: &str) -> String {
+    value.trim().trim_matches(['"', '\'']).to_string()
+}
+
+// `key: value` fields of the front matter. Lists, inline `[a, b]` or as
+// `- a` lines, give a key several values.
+pub fn front_matter(text: &str) -> Vec<(String, Vec<String>)> {
+    let len = front_matter_span(text.lines());
+    let mut fields: Vec<(String, Vec<String>)> = Vec::new();
 
-        for (pattern, replacement) in DEFAULT_RULES.iter() {
-            let re = Regex::new(pattern).unwrap();
-            parsed_line = re.replace_all(&parsed_line, *replacement).to_string();
+    for line in text.lines().take(len.saturating_sub(1)).skip(1) {
+        if let Some(item) = line.trim().strip_prefix("- ") {
+            if let Some((_, values)) = fields.last_mut() {
+                values.push(unquote(item));
+            }
+        } else if let Some((key, value)) = line.split_once(':') {
+            let value = value.trim();
+            let values = match value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+                Some(list) => list.split(',').map(unquote).filter(|v| !v.is_empty()).collect(),
+                None if value.is_empty() => Vec::new(),
+                None => vec![unquote(value)],
+            };
+            fields.push((key.trim().to_string(), values));
         }
-        
-        text[i].render = parsed_line;
     }
+
+    fields
+}
+
+// Tags of a note, from the front matter `tags` field and inline `#tags`
+// outside of code.
+pub fn tags(text: &str) -> Vec<String> {
+    let mut tags: Vec<String> = front_matter(text).into_iter()
+        .filter(|(key, _)| key == "tags" || key == "tag")
+        .flat_map(|(_, values)| values)
+        .map(|tag| tag.trim_start_matches('#').to_string())
+        .collect();
+
+    static RE: OnceLock<Regex> = OnceLock::new();
+    static CODE: OnceLock<Regex> = OnceLock::new();
+    let re = regex(&RE, TAG_RULE);
+    let code = regex(&CODE, r#"`[^`]*`"#);
+    let mut in_code = false;
+    for line in text.lines().skip(front_matter_span(text.lines())) {
+        if is_fence(line) {
+            in_code = !in_code;
+            continue;
+        }
+        if in_code {
+            continue;
+        }
+        for caps in re.captures_iter(&code.replace_all(line, "")) {
+            tags.push(caps[2][1..].to_string());
+        }
+    }
+
+    let mut seen = Vec::new();
+    tags.retain(|tag| {
+        let lower = tag.to_lowercase();
+        let new = !tag.is_empty() && !seen.contains(&lower);
+        seen.push(lower);
+        new
+    });
+    tags
+}
+
+// Marks the lines that belong to fenced code blocks, fences included.
+pub fn code_lines(rows: &[Line]) -> Vec<bool> {
+    let mut in_code = false;
+    rows.iter().map(|line| {
+        if is_fence(&line.row) {
+            in_code = !in_code;
+            return true;
+        }
+        in_code
+    }).collect()
 }
 
 pub fn parse_line_to_markdown(text: String) -> String {
-        let mut parsed_line = text;
+    render_line(&text, Conceal::Full).0
+}
+
+static TASK: &str = r#"^(\s*[-*+]\s\[)([ xX])(\]\s?)(.*)$"#;
+static ITEM: &str = r#"^(\s*[-*+]\s)(.*)$"#;
+static DONE_STAMP: &str = r#"\s@done\(\d{4}-\d{2}-\d{2}\)$"#;
+static TASK_RE: OnceLock<Regex> = OnceLock::new();
+
+// Some(true) for a checked task, Some(false) for an open one.
+pub fn task_state(line: &str) -> Option<bool> {
+    let re = regex(&TASK_RE, TASK);
+    re.captures(line).map(|caps| &caps[2] != " ")
+}
+
+// Flips the checkbox on `line`, turning a plain list item into an open task.
+// Checking a task appends `@done(date)` when a date is given. Like the
+// checkbox rendering, this only knows bulleted tasks, so ordered items and
+// other lines give None.
+pub fn toggle_task(line: &str, stamp: Option<Date>) -> Option<String> {
+    static DONE: OnceLock<Regex> = OnceLock::new();
+    let re = regex(&TASK_RE, TASK);
+    let done = regex(&DONE, DONE_STAMP);
+
+    if let Some(caps) = re.captures(line) {
+        let text = done.replace(&caps[4], "").to_string();
+        if &caps[2] == " " {
+            let stamp = stamp.map(|date| format!(" @done({})", date)).unwrap_or_default();
+            return Some(format!("{}x{}{}{}", &caps[1], &caps[3], text, stamp));
+        }
+        return Some(format!("{} {}{}", &caps[1], &caps[3], text));
+    }
+
+    static RE: OnceLock<Regex> = OnceLock::new();
+    let item = regex(&RE, ITEM);
+    item.captures(line).map(|caps| format!("{}[ ] {}", &caps[1], &caps[2]))
+}
+
+static LIST_PREFIX: &str = r#"^(\s*)(?:([-*+])\s(\[[ xX]\]\s)?|(\d+)([.)])\s|>\s?)"#;
+
+#[derive(PartialEq, Eq)]
+pub enum Marker {
+    Bullet(char),
+    Task(char),
+    Ordered(usize, char),
+    Quote,
+}
+
+pub struct ListPrefix {
+    pub indent: String,
+    pub marker: Marker,
+    pub len: usize,
+}
+
+impl ListPrefix {
+    // The prefix a new item following this one starts with.
+    pub fn continuation(&self) -> String {
+        match self.marker {
+            Marker::Bullet(c) => format!("{}{} ", self.indent, c),
+            Marker::Task(c) => format!("{}{} [ ] ", self.indent, c),
+            Marker::Ordered(n, c) => format!("{}{}{} ", self.indent, n + 1, c),
+            Marker::Quote => format!("{}> ", self.indent),
+        }
+    }
+}
+
+pub fn list_prefix(line: &str) -> Option<ListPrefix> {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    let re = regex(&RE, LIST_PREFIX);
+    let caps = re.captures(line)?;
+    let indent = caps[1].to_string();
+
+    let marker = if let Some(bullet) = caps.get(2) {
+        let c = bullet.as_str().chars().next().unwrap();
+        if caps.get(3).is_some() { Marker::Task(c) } else { Marker::Bullet(c) }
+    } else if let Some(num) = caps.get(4) {
+        Marker::Ordered(num.as_str().parse().unwrap_or(1), caps[5].chars().next().unwrap())
+    } else {
+        Marker::Quote
+    };
+
+    Some(ListPrefix { indent, marker, len: caps[0].len() })
+}
+
+fn ordered_at(line: &str) -> Option<(usize, char)> {
+    match list_prefix(line) {
+        Some(ListPrefix { indent, marker: Marker::Ordered(_, c), .. }) => Some((indent.len(), c)),
+        _ => None,
+    }
+}
+
+fn indent_of(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+// Renumbers the ordered list `idx` belongs to, returning the lines that changed.
+pub fn renumber(rows: &[Line], idx: usize) -> Vec<(usize, String)> {
+    let Some((indent, delim)) = rows.get(idx).and_then(|line| ordered_at(&line.row)) else {
+        return Vec::new();
+    };
+    let same_list = |row: &str| ordered_at(row) == Some((indent, delim));
+    let nested = |row: &str| !row.trim().is_empty() && indent_of(row) > indent;
 
-        for (pattern, replacement) in DEFAULT_RULES.iter() {
-            let re = Regex::new(pattern).unwrap();
-            parsed_line = re.replace_all(&parsed_line, *replacement).to_string();
+    let mut start = idx;
+    let mut i = idx;
+    while i > 0 && (same_list(&rows[i - 1].row) || nested(&rows[i - 1].row)) {
+        i -= 1;
+        if same_list(&rows[i].row) {
+            start = i;
         }
-        
-        return parsed_line;
+    }
+
+    let mut number = match list_prefix(&rows[start].row) {
+        Some(ListPrefix { marker: Marker::Ordered(n, _), .. }) => n,
+        _ => 1,
+    };
+
+    let mut changed = Vec::new();
+    for (i, line) in rows.iter().enumerate().skip(start) {
+        if same_list(&line.row) {
+            let prefix = list_prefix(&line.row).unwrap();
+            let row = format!("{}{}{} {}", prefix.indent, number, delim, &line.row[prefix.len..]);
+            if row != line.row {
+                changed.push((i, row));
+            }
+            number += 1;
+        } else if !nested(&line.row) {
+            break;
+        }
+    }
+
+    changed
+}
+
+pub struct Heading {
+    pub line: usize,
+    pub level: usize,
+    pub text: String,
+}
+
+pub fn is_fence(line: &str) -> bool {
+    let line = line.trim_start();
+    line.starts_with("```") || line.starts_with("~~~")
+}
+
+fn setext_level(line: &str) -> Option<usize> {
+    let line = line.trim();
+    if line.is_empty() {
+        None
+    } else if line.chars().all(|c| c == '=') {
+        Some(1)
+    } else if line.chars().all(|c| c == '-') {
+        Some(2)
+    } else {
+        None
+    }
+}
+
+// Every ATX and setext heading outside of fenced code blocks.
+pub fn headings(rows: &[Line]) -> Vec<Heading> {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    let re = regex(&RE, r#"^ {0,3}(#{1,6})(?:\s+(.*?))?(?:\s+#+)?\s*$"#);
+    let mut headings = Vec::new();
+    let mut in_code = false;
+
+    for (i, line) in rows.iter().enumerate().skip(front_matter_len(rows)) {
+        if is_fence(&line.row) {
+            in_code = !in_code;
+            continue;
+        }
+        if in_code {
+            continue;
+        }
+
+        if let Some(caps) = re.captures(&line.row) {
+            let text = caps.get(2).map_or("", |m| m.as_str()).to_string();
+            headings.push(Heading { line: i, level: caps[1].len(), text });
+        } else if let Some(level) = rows.get(i + 1).and_then(|next| setext_level(&next.row)) {
+            let text = line.row.trim();
+            if !text.is_empty() && setext_level(text).is_none() && list_prefix(text).is_none() && !is_fence(text) {
+                headings.push(Heading { line: i, level, text: text.to_string() });
+            }
+        }
+    }
+
+    headings
+}
+
+// Index into `headings` of the section `line` belongs to.
+pub fn section_at(headings: &[Heading], line: usize) -> Option<usize> {
+    headings.iter().rposition(|h| h.line <= line)
+}
+
+// First line after the section started by `headings[idx]`, subsections included.
+pub fn section_end(headings: &[Heading], idx: usize, total: usize) -> usize {
+    let level = headings[idx].level;
+    headings[idx + 1..].iter().find(|h| h.level <= level).map_or(total, |h| h.line)
+}
+
+// The edits that move the section started by `headings[idx]` up (-1) or down
+// (1) a level, as (start, end, heading) line replacements from the bottom up
+// so dropping setext underlines keeps earlier line numbers valid. None when a
+// heading would leave levels 1 to 6.
+pub fn shift_section(rows: &[Line], headings: &[Heading], idx: usize, delta: i32) -> Option<Vec<(usize, usize, String)>> {
+    let end = section_end(headings, idx, rows.len());
+    let shifted: Vec<&Heading> = headings[idx..].iter().take_while(|h| h.line < end).collect();
+    if shifted.iter().any(|h| h.level as i32 + delta < 1 || h.level as i32 + delta > 6) {
+        return None;
+    }
+
+    Some(shifted.iter().rev().map(|heading| {
+        let level = (heading.level as i32 + delta) as usize;
+        let end = if is_setext_underline(rows, heading.line + 1) { heading.line + 2 } else { heading.line + 1 };
+        (heading.line, end, format!("{} {}", "#".repeat(level), heading.text))
+    }).collect())
+}
+
+// The lines `first..second` and `second..last` that trade places when the
+// section `headings[idx]` moves past its previous or next sibling.
+pub fn sibling_swap(headings: &[Heading], idx: usize, down: bool, total: usize) -> Option<(usize, usize, usize)> {
+    let level = headings[idx].level;
+    let start = headings[idx].line;
+    let end = section_end(headings, idx, total);
+
+    if down {
+        let sibling = headings.iter().position(|h| h.line == end && h.level == level)?;
+        Some((start, end, section_end(headings, sibling, total)))
+    } else {
+        let sibling = headings[..idx].iter().rposition(|h| h.level <= level).filter(|i| headings[*i].level == level)?;
+        Some((headings[sibling].line, start, end))
+    }
+}
+
+// Whether `line` is the underline of a setext heading.
+pub fn is_setext_underline(rows: &[Line], line: usize) -> bool {
+    line > 0 && line < rows.len() && setext_level(&rows[line].row).is_some() && headings(&rows[line - 1..=line]).first().is_some_and(|h| h.line == 0)
+}
+
+// The quote markers and list marker in front of a reflowed paragraph.
+struct BlockPrefix {
+    first: String,
+    // What the lines after the first start with.
+    rest: String,
+    quote: String,
+    body: usize,
+    item: bool,
+}
+
+fn block_prefix(line: &str) -> BlockPrefix {
+    let mut quote = 0;
+    while let Some(p) = list_prefix(&line[quote..]).filter(|p| matches!(p.marker, Marker::Quote)) {
+        quote += p.len;
+    }
+
+    let rest = &line[quote..];
+    let (len, item) = match list_prefix(rest) {
+        Some(p) => (p.len, true),
+        None => (rest.len() - rest.trim_start().len(), false),
+    };
+    let hanging = if item { " ".repeat(rest[..len].chars().count()) } else { rest[..len].to_string() };
+    BlockPrefix { first: line[..quote + len].to_string(), rest: format!("{}{}", &line[..quote], hanging), quote: line[..quote].replace(' ', ""), body: quote + len, item }
+}
+
+struct Paragraph<'a> {
+    prefix: BlockPrefix,
+    words: Vec<&'a str>,
+    // The word holding the cursor and the cursor's offset into it.
+    cursor: Option<(usize, usize)>,
+}
+
+// Fills lines up to `width` with the words of `para`, returning where the
+// cursor went if it was in there.
+fn fill(para: Paragraph, width: usize, suffix: &str, out: &mut Vec<String>) -> Option<(usize, usize)> {
+    let mut moved = None;
+    let mut line = para.prefix.first.clone();
+    let mut empty = true;
+    for (n, word) in para.words.iter().enumerate() {
+        if !empty && line.chars().count() + 1 + word.chars().count() > width {
+            out.push(std::mem::replace(&mut line, para.prefix.rest.clone()));
+            empty = true;
+        }
+        if !empty {
+            line.push(' ');
+        }
+        if let Some((_, offset)) = para.cursor.filter(|(w, _)| *w == n) {
+            moved = Some((out.len(), line.len() + offset.min(word.len())));
+        }
+        line.push_str(word);
+        empty = false;
+    }
+    line.push_str(suffix);
+    out.push(line);
+    moved
+}
+
+// Rewraps paragraphs in `lines` to `width` columns, keeping list markers,
+// quote prefixes and hard breaks. Blank lines, headings, rules, tables and
+// the lines marked in `keep` stay as they are. Also returns where `cursor`,
+// a line and byte offset, ends up.
+pub fn reflow(lines: &[String], keep: &[bool], width: usize, cursor: (usize, usize)) -> (Vec<String>, (usize, usize)) {
+    static HEADING: OnceLock<Regex> = OnceLock::new();
+    let heading = regex(&HEADING, r#"^#{1,6}(\s|$)"#);
+    let mut out = Vec::new();
+    let mut moved = cursor;
+    let mut para: Option<Paragraph> = None;
+
+    for (i, line) in lines.iter().enumerate() {
+        let inner = line.trim_start_matches(|c: char| c == '>' || c.is_whitespace());
+        let rule = setext_level(inner).is_some() && inner.len() >= 3;
+        let underlined = lines.get(i + 1).is_some_and(|next| setext_level(next).is_some());
+        if keep.get(i).copied().unwrap_or(false) || inner.is_empty() || heading.is_match(inner) || inner.starts_with('|') || rule || underlined {
+            if let Some(p) = para.take() {
+                moved = fill(p, width, "", &mut out).unwrap_or(moved);
+            }
+            if i == cursor.0 {
+                moved = (out.len(), cursor.1);
+            }
+            out.push(line.clone());
+            continue;
+        }
+
+        let prefix = block_prefix(line);
+        let body = prefix.body;
+        if para.as_ref().is_none_or(|p| prefix.item || p.prefix.quote != prefix.quote) {
+            if let Some(p) = para.take() {
+                moved = fill(p, width, "", &mut out).unwrap_or(moved);
+            }
+            para = Some(Paragraph { words: Vec::new(), cursor: None, prefix });
+        }
+
+        let p = para.as_mut().unwrap();
+        for word in line[body..].split_whitespace() {
+            let start = word.as_ptr() as usize - line.as_ptr() as usize;
+            if i == cursor.0 && p.cursor.is_none() && cursor.1 <= start + word.len() {
+                p.cursor = Some((p.words.len(), cursor.1.saturating_sub(start)));
+            }
+            p.words.push(word);
+        }
+        if i == cursor.0 && p.cursor.is_none() && !p.words.is_empty() {
+            p.cursor = Some((p.words.len() - 1, usize::MAX));
+        }
+
+        // A line ending in two spaces or a backslash is a hard break
+        if line.ends_with("  ") || line.ends_with('\\') {
+            let suffix = if line.ends_with("  ") { "  " } else { "" };
+            if let Some(p) = para.take() {
+                moved = fill(p, width, suffix, &mut out).unwrap_or(moved);
+            }
+        }
+    }
+    if let Some(p) = para.take() {
+        moved = fill(p, width, "", &mut out).unwrap_or(moved);
+    }
+
+    (out, moved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::screen::strip_escapes;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(String::from).collect()
+    }
+
+    fn reflowed(text: &str, width: usize) -> Vec<String> {
+        let lines = lines(text);
+        reflow(&lines, &vec![false; lines.len()], width, (0, 0)).0
+    }
+
+    #[test]
+    fn lines_starting_with_a_tag_are_not_headings() {
+        let line = parse_line("#work #home");
+        assert!(line.marker.is_none());
+        assert_eq!(strip_escapes(&render_line("#work #home", Conceal::Full).0), "#work #home");
+        assert_eq!(tags("#work #home"), vec!["work", "home"]);
+
+        assert_eq!(parse_line("# Title").marker, Some((0, 2)));
+        assert_eq!(parse_line("#").marker, Some((0, 1)));
+        assert_eq!(strip_escapes(&render_line("## Title", Conceal::Full).0), "Title");
+    }
+
+    #[test]
+    fn render_maps_every_byte_to_its_column() {
+        let row = "a **b** [c](d) `e`";
+        let identity: Vec<usize> = (0..=row.len()).collect();
+
+        let (raw, cols) = render_line(row, Conceal::Raw);
+        assert_eq!((raw.as_str(), cols), (row, identity.clone()));
+
+        let (partial, cols) = render_line(row, Conceal::Partial);
+        assert_eq!((strip_escapes(&partial).as_str(), cols), (row, identity));
+
+        let (full, cols) = render_line(row, Conceal::Full);
+        assert_eq!(strip_escapes(&full), "a b c e");
+        assert_eq!(cols, vec![0, 1, 2, 2, 2, 3, 3, 3, 4, 4, 5, 5, 5, 5, 5, 6, 6, 7, 7]);
+    }
+
+    #[test]
+    fn toggling_tasks_flips_the_checkbox() {
+        let checked = toggle_task("- [ ] buy milk", None).unwrap();
+        assert_eq!(checked, "- [x] buy milk");
+        assert_eq!(task_state(&checked), Some(true));
+        let open = toggle_task(&checked, None).unwrap();
+        assert_eq!(open, "- [ ] buy milk");
+        assert_eq!(task_state(&open), Some(false));
+        assert_eq!(toggle_task("  * [X] nested", None).as_deref(), Some("  * [ ] nested"));
+    }
+
+    #[test]
+    fn toggling_tasks_stamps_and_clears_the_done_date() {
+        let date = Date::parse("2026-03-05");
+        let checked = toggle_task("- [ ] ship it", date).unwrap();
+        assert_eq!(checked, "- [x] ship it @done(2026-03-05)");
+        assert_eq!(toggle_task(&checked, date).as_deref(), Some("- [ ] ship it"));
+    }
+
+    #[test]
+    fn toggling_a_bullet_makes_it_a_task() {
+        assert_eq!(toggle_task("+ idea", None).as_deref(), Some("+ [ ] idea"));
+        assert_eq!(task_state("+ idea"), None);
+    }
+
+    #[test]
+    fn toggling_leaves_ordered_items_and_text_alone() {
+        assert_eq!(toggle_task("1. first", None), None);
+        assert_eq!(toggle_task("1. [ ] first", None), None);
+        assert_eq!(toggle_task("plain text", None), None);
+        assert_eq!(task_state("1. [x] first"), None);
+    }
+
+    fn rows(text: &str) -> Vec<Line> {
+        text.lines().map(|line| Line::new(line.to_string())).collect()
+    }
+
+    #[test]
+    fn enter_continues_each_kind_of_list() {
+        let continued = |line: &str| list_prefix(line).map(|p| p.continuation());
+        assert_eq!(continued("* one").as_deref(), Some("* "));
+        assert_eq!(continued("  - one").as_deref(), Some("  - "));
+        assert_eq!(continued("- [x] done").as_deref(), Some("- [ ] "));
+        assert_eq!(continued("9. nine").as_deref(), Some("10. "));
+        assert_eq!(continued("3) three").as_deref(), Some("4) "));
+        assert_eq!(continued("> quoted").as_deref(), Some("> "));
+        assert_eq!(continued("plain"), None);
+        assert_eq!(list_prefix("12. twelve").map(|p| p.len), Some(4));
+    }
+
+    #[test]
+    fn renumber_skips_over_nested_lists() {
+        let text = rows("1. one\n1. two\n   1. inner\n   5. inner\n   - bullet\n7. three\n1) other");
+        assert_eq!(renumber(&text, 5), vec![(1, "2. two".to_string()), (5, "3. three".to_string())]);
+        assert_eq!(renumber(&text, 2), vec![(3, "   2. inner".to_string())]);
+        assert_eq!(renumber(&text, 4), vec![]);
+    }
+
+    #[test]
+    fn renumber_starts_from_the_first_number_and_stops_at_a_blank_line() {
+        let text = rows("text\n4. four\n4. five\n\n4. again");
+        assert_eq!(renumber(&text, 1), vec![(2, "5. five".to_string())]);
+        assert_eq!(renumber(&text, 4), vec![]);
+    }
+
+    const OUTLINE: &str = "intro\n# A\ntext\n## B\n```\n# not a heading\n```\n### C\n## D\nSetext\n======\ntail";
+
+    #[test]
+    fn headings_skip_code_and_read_setext_underlines() {
+        let rows = rows(OUTLINE);
+        let headings = headings(&rows);
+        let found: Vec<(usize, usize, &str)> = headings.iter().map(|h| (h.line, h.level, h.text.as_str())).collect();
+        assert_eq!(found, vec![(1, 1, "A"), (3, 2, "B"), (7, 3, "C"), (8, 2, "D"), (9, 1, "Setext")]);
+        assert!(is_setext_underline(&rows, 10));
+        assert!(!is_setext_underline(&rows, 2));
+    }
+
+    #[test]
+    fn sections_nest_and_run_to_the_end_of_the_file() {
+        let rows = rows(OUTLINE);
+        let headings = headings(&rows);
+        assert_eq!(section_at(&headings, 0), None);
+        assert_eq!(section_at(&headings, 5), Some(1));
+        assert_eq!(section_at(&headings, 11), Some(4));
+
+        let ends: Vec<usize> = (0..headings.len()).map(|i| section_end(&headings, i, rows.len())).collect();
+        assert_eq!(ends, vec![9, 8, 8, 9, 12]);
+    }
+
+    #[test]
+    fn shifting_a_section_takes_its_subheadings_along() {
+        let rows = rows(OUTLINE);
+        let headings = headings(&rows);
+        let edits = |idx, delta| shift_section(&rows, &headings, idx, delta);
+
+        let demoted = vec![(8, 9, "### D".to_string()), (7, 8, "#### C".to_string()), (3, 4, "### B".to_string()), (1, 2, "## A".to_string())];
+        assert_eq!(edits(0, 1), Some(demoted));
+        assert_eq!(edits(4, 1), Some(vec![(9, 11, "## Setext".to_string())]));
+        assert_eq!(edits(0, -1), None);
+        assert_eq!(edits(2, 4), None);
+    }
+
+    #[test]
+    fn sections_swap_only_with_siblings() {
+        let rows = rows(OUTLINE);
+        let headings = headings(&rows);
+        let swap = |idx, down| sibling_swap(&headings, idx, down, rows.len());
+
+        assert_eq!(swap(1, true), Some((3, 8, 9)));
+        assert_eq!(swap(3, false), Some((3, 8, 9)));
+        assert_eq!(swap(0, true), Some((1, 9, 12)));
+        assert_eq!(swap(4, false), Some((1, 9, 12)));
+        assert_eq!(swap(0, false), None);
+        assert_eq!(swap(2, true), None);
+    }
+
+    #[test]
+    fn block_prefix_of_lists_and_quotes() {
+        let item = block_prefix("- [ ] task");
+        assert_eq!((item.first.as_str(), item.rest.as_str(), item.item), ("- [ ] ", "      ", true));
+        let quoted = block_prefix("> > 1. item");
+        assert_eq!((quoted.first.as_str(), quoted.rest.as_str(), quoted.quote.as_str()), ("> > 1. ", "> >    ", ">>"));
+        let plain = block_prefix("  text");
+        assert_eq!((plain.first.as_str(), plain.rest.as_str(), plain.item, plain.body), ("  ", "  ", false, 2));
+    }
+
+    #[test]
+    fn reflow_joins_and_fills_paragraphs() {
+        assert_eq!(reflowed("one two\nthree four five\n\nsix", 14), lines("one two three\nfour five\n\nsix"));
+    }
+
+    #[test]
+    fn reflow_hangs_list_items_and_keeps_them_apart() {
+        assert_eq!(reflowed("- alpha beta gamma\n- delta", 12), lines("- alpha beta\n  gamma\n- delta"));
+    }
+
+    #[test]
+    fn reflow_keeps_quote_markers() {
+        assert_eq!(reflowed("> one two three\n> four", 10), lines("> one two\n> three\n> four"));
+    }
+
+    #[test]
+    fn reflow_stops_at_hard_breaks_and_headings() {
+        assert_eq!(reflowed("one  \ntwo\\\nthree\n# Title\nfour", 79), lines("one  \ntwo\\\nthree\n# Title\nfour"));
+        assert_eq!(reflowed("one  \ntwo\nthree", 79), lines("one  \ntwo three"));
+    }
+
+    #[test]
+    fn reflow_leaves_kept_lines_alone() {
+        let text = lines("```\na b c d e f\n```\ng h");
+        let keep = [true, true, true, false];
+        assert_eq!(reflow(&text, &keep, 4, (0, 0)).0, lines("```\na b c d e f\n```\ng h"));
+    }
+
+    #[test]
+    fn reflow_follows_the_cursor() {
+        let text = lines("one two\nthree four");
+        assert_eq!(reflow(&text, &[false, false], 79, (1, 6)).1, (0, 14));
+    }
 }
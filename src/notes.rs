@@ -0,0 +1,437 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use regex::Regex;
+
+use crate::date::{strftime, Date};
+use crate::markdown::regex;
+
+pub enum Link {
+    // `[[Note Name]]`, `[[Note Name#Heading]]` or `[[Note Name|alias]]`
+    Wiki { target: String, heading: Option<String> },
+    // `[text](other.md)` or `[text](other.md#heading)`
+    File { path: String, heading: Option<String> },
+    Url(String),
+}
+
+static WIKI_LINK: &str = r#"\[\[([^\]|#]+)(?:#([^\]|]+))?(?:\|[^\]]+)?\]\]"#;
+static MD_LINK: &str = r#"!?\[[^\]]*\]\(([^)\s]+)\)"#;
+
+fn split_heading(target: &str) -> (String, Option<String>) {
+    match target.split_once('#') {
+        Some((path, heading)) => (path.to_string(), Some(heading.to_string())),
+        None => (target.to_string(), None),
+    }
+}
+
+// Every link on `row` with the byte range it covers.
+pub fn links(row: &str) -> Vec<(usize, usize, Link)> {
+    let mut links = Vec::new();
+
+    static WIKI: OnceLock<Regex> = OnceLock::new();
+    static MD: OnceLock<Regex> = OnceLock::new();
+    let wiki = regex(&WIKI, WIKI_LINK);
+    for caps in wiki.captures_iter(row) {
+        let m = caps.get(0).unwrap();
+        let target = caps[1].trim().to_string();
+        let heading = caps.get(2).map(|h| h.as_str().trim().to_string());
+        links.push((m.start(), m.end(), Link::Wiki { target, heading }));
+    }
+
+    let md = regex(&MD, MD_LINK);
+    for caps in md.captures_iter(row) {
+        let m = caps.get(0).unwrap();
+        if links.iter().any(|(start, end, _)| m.start() < *end && *start < m.end()) {
+            continue;
+        }
+
+        let url = &caps[1];
+        let link = if url.contains("://") || url.starts_with("mailto:") {
+            Link::Url(url.to_string())
+        } else {
+            let (path, heading) = split_heading(url);
+            Link::File { path, heading }
+        };
+        links.push((m.start(), m.end(), link));
+    }
+
+    links
+}
+
+pub fn link_at(row: &str, col: usize) -> Option<Link> {
+    links(row).into_iter().find(|(start, end, _)| *start <= col && col < *end).map(|(_, _, link)| link)
+}
+
+// Finds the note a wiki link names: `<root>/<name>.md` first, then any
+// note with that name below the root, ignoring case.
+pub fn resolve_wiki(root: &Path, target: &str) -> PathBuf {
+    let name = if target.ends_with(".md") { target.to_string() } else { format!("{}.md", target) };
+    let direct = root.join(&name);
+    if direct.exists() {
+        return direct;
+    }
+
+    find_note(root, &name.to_lowercase()).unwrap_or(direct)
+}
+
+fn find_note(dir: &Path, name: &str) -> Option<PathBuf> {
+    let entries = fs::read_dir(dir).ok()?;
+    let mut dirs = Vec::new();
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let file_name = entry.file_name().to_string_lossy().to_lowercase();
+        if file_name.starts_with('.') {
+            continue;
+        }
+        if path.is_dir() {
+            dirs.push(path);
+        } else if file_name == name {
+            return Some(path);
+        }
+    }
+
+    dirs.iter().find_map(|dir| find_note(dir, name))
+}
+
+// Resolves a relative markdown link against the linking note's directory,
+// or against the notes root when it starts with `/`.
+pub fn resolve_file(root: &Path, current: Option<&str>, path: &str) -> PathBuf {
+    if let Some(path) = path.strip_prefix('/') {
+        return root.join(path);
+    }
+
+    let dir = current.and_then(|c| Path::new(c).parent()).map(|p| p.to_path_buf()).unwrap_or_else(|| root.to_path_buf());
+    dir.join(path)
+}
+
+// The template new notes start from, `.next/templates/<name>.md` under the
+// notes root. `default` and `journal` have built-in fallbacks.
+pub fn template(root: &Path, name: &str) -> Option<String> {
+    if let Ok(text) = fs::read_to_string(root.join(".next").join("templates").join(format!("{}.md", name))) {
+        return Some(text);
+    }
+    match name {
+        "default" => Some("# {{title}}\n\n{{cursor}}".to_string()),
+        "journal" => Some("# {{date}}\n\n{{cursor}}".to_string()),
+        _ => None,
+    }
+}
+
+// What the built-in template variables expand to.
+pub struct TemplateContext {
+    pub title: String,
+    pub filename: String,
+    pub date: Date,
+    // Seconds since midnight.
+    pub time: u32,
+}
+
+static TEMPLATE_VAR: &str = r#"\{\{\s*([\w-]+)(?::([^}]*))?\s*\}\}"#;
+static TEMPLATE_RE: OnceLock<Regex> = OnceLock::new();
+static BUILTIN_VARS: [&str; 5] = ["title", "filename", "date", "time", "cursor"];
+
+// Custom `{{field}}` variables of a template, asked for when a note is created.
+pub fn template_fields(template: &str) -> Vec<String> {
+    let re = regex(&TEMPLATE_RE, TEMPLATE_VAR);
+    let mut fields: Vec<String> = Vec::new();
+    for caps in re.captures_iter(template) {
+        let name = caps[1].to_string();
+        if !BUILTIN_VARS.contains(&name.as_str()) && !fields.contains(&name) {
+            fields.push(name);
+        }
+    }
+    fields
+}
+
+// Expands the variables of `template`, returning the text and the line and
+// column `{{cursor}}` stood at.
+pub fn expand_template(template: &str, ctx: &TemplateContext, fields: &[(String, String)]) -> (String, Option<(usize, usize)>) {
+    let re = regex(&TEMPLATE_RE, TEMPLATE_VAR);
+    let mut cursor = None;
+    let mut text = String::new();
+    let mut pos = 0;
+
+    for caps in re.captures_iter(template) {
+        let m = caps.get(0).unwrap();
+        text.push_str(&template[pos..m.start()]);
+        pos = m.end();
+
+        let format = caps.get(2).map(|f| f.as_str());
+        match &caps[1] {
+            "title" => text.push_str(&ctx.title),
+            "filename" => text.push_str(&ctx.filename),
+            "date" => text.push_str(&strftime(ctx.date, ctx.time, format.unwrap_or("%Y-%m-%d"))),
+            "time" => text.push_str(&strftime(ctx.date, ctx.time, format.unwrap_or("%H:%M"))),
+            "cursor" => {
+                let line = text.matches('\n').count();
+                cursor = Some((line, text.len() - text.rfind('\n').map_or(0, |i| i + 1)));
+            },
+            name => text.push_str(fields.iter().find(|(field, _)| field == name).map_or("", |(_, value)| value.as_str())),
+        }
+    }
+    text.push_str(&template[pos..]);
+
+    (text, cursor)
+}
+
+// Daily notes live in `journal/` under the notes root, one per day.
+pub fn journal_path(root: &Path, date: Date) -> PathBuf {
+    root.join("journal").join(format!("{}.md", date))
+}
+
+// The day a journal note is for.
+pub fn journal_date(path: &Path) -> Option<Date> {
+    if path.parent()?.file_name()? != "journal" {
+        return None;
+    }
+    Date::parse(&note_title(path))
+}
+
+// Links in `line` of the note at `source` that point at the note `target`.
+fn links_to(root: &Path, source: &Path, line: &str, target: &Path) -> Vec<(usize, usize)> {
+    let name = note_title(target).to_lowercase();
+    let is_target = |path: PathBuf| fs::canonicalize(path).is_ok_and(|path| path == target);
+
+    links(line).into_iter().filter(|(_, _, link)| match link {
+        Link::Wiki { target, .. } => {
+            let target = target.strip_suffix(".md").unwrap_or(target);
+            if target.contains('/') {
+                is_target(root.join(format!("{}.md", target)))
+            } else {
+                // A note of the same name elsewhere is not the target
+                target.to_lowercase() == name && is_target(resolve_wiki(root, target))
+            }
+        },
+        Link::File { path, .. } if !path.is_empty() => is_target(resolve_file(root, source.to_str(), path)),
+        _ => false,
+    }).map(|(start, end, _)| (start, end)).collect()
+}
+
+// Lines of `text`, the note at `source`, that link to the note at `target`,
+// as (line, column, line text).
+pub fn backlinks(root: &Path, source: &Path, text: &str, target: &Path) -> Vec<(usize, usize, String)> {
+    let Ok(target) = fs::canonicalize(target) else {
+        return Vec::new();
+    };
+
+    text.lines().enumerate().filter_map(|(i, line)| {
+        links_to(root, source, line, &target).first().map(|(start, _)| (i, *start, line.trim().to_string()))
+    }).collect()
+}
+
+// Points every link in `text` at `target` to the note `name` in the same
+// directory instead, returning the new text and how many links changed.
+pub fn rewrite_links(root: &Path, source: &Path, text: &str, target: &Path, name: &str) -> (String, usize) {
+    let Ok(target) = fs::canonicalize(target) else {
+        return (text.to_string(), 0);
+    };
+
+    let mut count = 0;
+    let mut lines = Vec::new();
+    for line in text.split('\n') {
+        let mut line = line.to_string();
+        for (start, end) in links_to(root, source, &line, &target).into_iter().rev() {
+            let link = &line[start..end];
+            // Only the file name part of the target changes, headings and aliases stay
+            let (from, to) = if let Some(inner) = link.strip_prefix("[[") {
+                (start + 2, start + 2 + inner.find(['#', '|', ']']).unwrap_or(inner.len()))
+            } else {
+                let url = start + link.rfind("](").unwrap_or(0) + 2;
+                (url, url + line[url..end - 1].find('#').unwrap_or(end - 1 - url))
+            };
+
+            let old = line[from..to].trim();
+            let dir = old.rfind('/').map(|i| &old[..=i]).unwrap_or("");
+            let ext = if old.ends_with(".md") || !link.starts_with("[[") { ".md" } else { "" };
+            line.replace_range(from..to, &format!("{}{}{}", dir, name, ext));
+            count += 1;
+        }
+        lines.push(line);
+    }
+
+    (lines.join("\n"), count)
+}
+
+// A hidden file next to `path` to stage its new text in.
+fn staging_path(path: &Path) -> PathBuf {
+    let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    path.with_file_name(format!(".{}.next-tmp", name))
+}
+
+// Gives each file of `files`, as (path, text now, new text), its new text
+// and then moves the note `from` to `to`, all or nothing. New texts are
+// staged next to their files and renamed into place, and the files already
+// replaced get their old text back when a later step fails.
+pub fn write_batch(files: &[(PathBuf, String, String)], from: &Path, to: &Path) -> io::Result<()> {
+    let mut staged = Vec::new();
+    for (path, _, text) in files {
+        let tmp = staging_path(path);
+        if let Err(err) = fs::write(&tmp, text) {
+            let _ = fs::remove_file(&tmp);
+            staged.iter().for_each(|tmp| { let _ = fs::remove_file(tmp); });
+            return Err(err);
+        }
+        staged.push(tmp);
+    }
+
+    let mut done: Vec<&(PathBuf, String, String)> = Vec::new();
+    let mut result = Ok(());
+    for (file, tmp) in files.iter().zip(staged.iter()) {
+        result = fs::rename(tmp, &file.0);
+        if result.is_err() {
+            break;
+        }
+        done.push(file);
+    }
+    if result.is_ok() {
+        result = fs::rename(from, to);
+    }
+
+    if result.is_err() {
+        staged.iter().for_each(|tmp| { let _ = fs::remove_file(tmp); });
+        for (path, old, _) in done {
+            let _ = fs::write(path, old);
+        }
+    }
+    result
+}
+
+// Title for a new note, the file name without its extension.
+pub fn note_title(path: &Path) -> String {
+    path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workspace::temp_workspace;
+
+    // A workspace with a `sub/` directory for notes below the root.
+    fn workspace(name: &str) -> PathBuf {
+        let root = temp_workspace(name);
+        fs::create_dir_all(root.join("sub")).unwrap();
+        root
+    }
+
+    #[test]
+    fn rewrite_skips_notes_of_the_same_name_elsewhere() {
+        let root = workspace("same-name");
+        fs::write(root.join("a.md"), "# A").unwrap();
+        fs::write(root.join("sub").join("a.md"), "# Sub A").unwrap();
+        let source = root.join("b.md");
+
+        let text = "[[a]] [[sub/a]] [x](a.md) [y](sub/a.md)";
+        let (text, count) = rewrite_links(&root, &source, text, &root.join("sub").join("a.md"), "z");
+        assert_eq!(text, "[[a]] [[sub/z]] [x](a.md) [y](sub/z.md)");
+        assert_eq!(count, 2);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn links_are_found_with_their_ranges() {
+        let found = links("See [[Plan#Goals|the plan]] and [notes](sub/n.md#top), ![img](a.png) <https://x.org> [x](https://x.org)");
+        assert_eq!(found.iter().map(|(start, end, _)| (*start, *end)).collect::<Vec<_>>(), vec![(4, 27), (32, 53), (55, 68), (85, 103)]);
+        assert!(matches!(&found[0].2, Link::Wiki { target, heading: Some(h) } if target == "Plan" && h == "Goals"));
+        assert!(matches!(&found[1].2, Link::File { path, heading: Some(h) } if path == "sub/n.md" && h == "top"));
+        assert!(matches!(&found[2].2, Link::File { path, heading: None } if path == "a.png"));
+        assert!(matches!(&found[3].2, Link::Url(url) if url == "https://x.org"));
+        assert!(link_at("[[a]] b", 5).is_none());
+        assert!(link_at("[[a]] b", 4).is_some());
+    }
+
+    #[test]
+    fn wiki_links_resolve_at_the_root_then_anywhere_below_it() {
+        let root = workspace("resolve");
+        fs::write(root.join("top.md"), "").unwrap();
+        fs::write(root.join("sub").join("Deep Note.md"), "").unwrap();
+        fs::create_dir_all(root.join(".next")).unwrap();
+        fs::write(root.join(".next").join("hidden.md"), "").unwrap();
+
+        assert_eq!(resolve_wiki(&root, "top"), root.join("top.md"));
+        assert_eq!(resolve_wiki(&root, "top.md"), root.join("top.md"));
+        assert_eq!(resolve_wiki(&root, "deep note"), root.join("sub").join("Deep Note.md"));
+        assert_eq!(resolve_wiki(&root, "hidden"), root.join("hidden.md"));
+        assert_eq!(resolve_wiki(&root, "missing"), root.join("missing.md"));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn backlinks_list_each_linking_line_once() {
+        let root = workspace("backlinks");
+        fs::write(root.join("a.md"), "# A").unwrap();
+        fs::write(root.join("sub").join("a.md"), "# Sub A").unwrap();
+        let source = root.join("sub").join("b.md");
+
+        let text = "intro\n  see [[A]] and [[a#Top]]\n[other](a.md)\n[up](../a.md)\n[[sub/a]]";
+        let found = backlinks(&root, &source, text, &root.join("a.md"));
+        assert_eq!(found, vec![(1, 6, "see [[A]] and [[a#Top]]".to_string()), (3, 0, "[up](../a.md)".to_string())]);
+
+        let found = backlinks(&root, &source, text, &root.join("sub").join("a.md"));
+        assert_eq!(found, vec![(2, 0, "[other](a.md)".to_string()), (4, 0, "[[sub/a]]".to_string())]);
+        assert!(backlinks(&root, &source, text, &root.join("gone.md")).is_empty());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    fn context() -> TemplateContext {
+        TemplateContext {
+            title: "Plan".to_string(),
+            filename: "plan.md".to_string(),
+            date: Date::parse("2026-03-05").unwrap(),
+            time: 3723,
+        }
+    }
+
+    #[test]
+    fn templates_expand_builtins_fields_and_formats() {
+        let template = "# {{title}} ({{ filename }})\n\
+                        {{date}} {{time}} {{date:%d/%m/%Y}} {{time:%H.%M.%S}}\n\
+                        {{owner}}: {{cursor}}{{unknown}}!";
+        assert_eq!(template_fields(template), vec!["owner", "unknown"]);
+
+        let fields = vec![("owner".to_string(), "Sam".to_string())];
+        let (text, cursor) = expand_template(template, &context(), &fields);
+        assert_eq!(text, "# Plan (plan.md)\n2026-03-05 01:02 05/03/2026 01.02.03\nSam: !");
+        assert_eq!(cursor, Some((2, 5)));
+    }
+
+    #[test]
+    fn templates_without_a_cursor_leave_it_unset() {
+        let (text, cursor) = expand_template("{{ title }}", &context(), &[]);
+        assert_eq!((text.as_str(), cursor), ("Plan", None));
+    }
+
+    #[test]
+    fn templates_come_from_the_workspace_or_the_builtins() {
+        let root = workspace("templates");
+        assert_eq!(template(&root, "default").as_deref(), Some("# {{title}}\n\n{{cursor}}"));
+        assert_eq!(template(&root, "meeting"), None);
+
+        fs::create_dir_all(root.join(".next").join("templates")).unwrap();
+        fs::write(root.join(".next").join("templates").join("meeting.md"), "## {{title}}").unwrap();
+        assert_eq!(template(&root, "meeting").as_deref(), Some("## {{title}}"));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn a_failed_batch_puts_the_files_back() {
+        let root = workspace("batch");
+        let (a, b) = (root.join("a.md"), root.join("b.md"));
+        fs::write(&a, "old a").unwrap();
+        fs::write(&b, "old b").unwrap();
+        let files = vec![(a.clone(), "old a".to_string(), "new a".to_string()), (b.clone(), "old b".to_string(), "new b".to_string())];
+
+        assert!(write_batch(&files, &root.join("missing.md"), &root.join("z.md")).is_err());
+        assert_eq!(fs::read_to_string(&a).unwrap(), "old a");
+        assert_eq!(fs::read_to_string(&b).unwrap(), "old b");
+        // No staging files are left behind
+        assert_eq!(fs::read_dir(&root).unwrap().flatten().filter(|entry| entry.path().is_file()).count(), 2);
+
+        write_batch(&files, &a, &root.join("z.md")).unwrap();
+        assert_eq!(fs::read_to_string(root.join("z.md")).unwrap(), "new a");
+        assert_eq!(fs::read_to_string(&b).unwrap(), "new b");
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -14,3 +14,357 @@ pub fn fill_row(c: char, row: u16) {
         } 
     }
 }
+
+// Cuts `width` display columns out of `text` starting at column `start`.
+// Escape sequences are kept wherever they fall so styling carries over into
+// the visible part instead of being sliced in half.
+pub fn slice_columns(text: &str, start: usize, width: usize) -> String {
+    let mut out = String::new();
+    let mut col = 0;
+    let mut styled = false;
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            out.push(c);
+            for e in chars.by_ref() {
+                out.push(e);
+                if e.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+            styled = true;
+            continue;
+        }
+
+        if col >= start && col - start < width {
+            out.push(c);
+        }
+        col += 1;
+    }
+
+    if styled {
+        out.push_str("\x1b[0m");
+    }
+    out
+}
+
+// Number of display columns `text` takes up, escape sequences excluded.
+pub fn visible_width(text: &str) -> usize {
+    text.chars().fold((0, false), |(n, esc), c| match c {
+        '\x1b' => (n, true),
+        c if esc => (n, !c.is_ascii_alphabetic()),
+        _ => (n + 1, false),
+    }).0
+}
+
+pub fn strip_escapes(text: &str) -> String {
+    let mut out = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            for e in chars.by_ref() {
+                if e.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+// The hanging indent kept when wrapping to `width`, none when it would leave
+// too little room for text.
+pub fn hanging_indent(indent: usize, width: usize) -> usize {
+    if indent + 8 > width { 0 } else { indent }
+}
+
+// Splits a line of `plain` text into display column ranges no wider than
+// `width`, breaking after spaces where possible when `linebreak` is set.
+// Rows after the first lose `indent` columns to the hanging indent.
+pub fn wrap_columns(plain: &str, width: usize, indent: usize, linebreak: bool) -> Vec<(usize, usize)> {
+    let chars: Vec<char> = plain.chars().collect();
+    let indent = hanging_indent(indent, width);
+    let mut rows = Vec::new();
+    let mut start = 0;
+
+    while chars.len() - start > if rows.is_empty() { width } else { width - indent } {
+        let room = if rows.is_empty() { width } else { width - indent };
+        let limit = start + room.max(1);
+        let end = if linebreak {
+            (start + 1..=limit).rev().find(|i| chars[i - 1] == ' ' && *i < chars.len()).unwrap_or(limit)
+        } else {
+            limit
+        };
+        rows.push((start, end));
+        start = end;
+    }
+    rows.push((start, chars.len()));
+
+    rows
+}
+
+// The first line a preview shows: the top line of the window it follows,
+// moved down while the lines from there to the cursor line `cx` take more
+// than `height` rows. `rows` gives the rows a line wraps to.
+pub fn preview_top(rowoffset: usize, cx: usize, height: usize, rows: impl Fn(usize) -> usize) -> usize {
+    let mut top = rowoffset.min(cx);
+    let mut heights: Vec<usize> = (top..=cx).map(rows).collect();
+    while top < cx && heights.iter().sum::<usize>() > height {
+        heights.remove(0);
+        top += 1;
+    }
+
+    top
+}
+
+#[derive(Clone, PartialEq)]
+struct Cell {
+    c: char,
+    // SGR parameters in effect when the cell was drawn, empty for none.
+    style: String,
+}
+
+const BLANK: Cell = Cell { c: ' ', style: String::new() };
+
+// A screen worth of cells. Frames are drawn with the same escape sequences
+// the terminal understands, and only their differences are sent out.
+pub struct Frame {
+    cols: u16,
+    rows: u16,
+    cells: Vec<Cell>,
+    // Where the cursor is left, if it is shown.
+    cursor: Option<(u16, u16)>,
+}
+
+impl Frame {
+    pub fn new(cols: u16, rows: u16) -> Frame {
+        Frame { cols, rows, cells: vec![BLANK; cols as usize * rows as usize], cursor: None }
+    }
+
+    // Plays `buf` onto the frame. Understands cursor positioning, erase to
+    // end of line, SGR styling and showing or hiding the cursor; text past
+    // the right edge is dropped.
+    pub fn draw(&mut self, buf: &str) {
+        let (mut row, mut col) = (0u16, 0u16);
+        let mut style = String::new();
+        let mut chars = buf.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\x1b' => {
+                    let mut seq = String::new();
+                    for e in chars.by_ref() {
+                        seq.push(e);
+                        if e.is_ascii_alphabetic() {
+                            break;
+                        }
+                    }
+                    let params = seq.trim_start_matches('[').trim_end_matches(|e: char| e.is_ascii_alphabetic());
+                    match seq.chars().last() {
+                        Some('H') => {
+                            let mut nums = params.split(';').map(|n| n.parse::<u16>().unwrap_or(1).max(1) - 1);
+                            row = nums.next().unwrap_or(0);
+                            col = nums.next().unwrap_or(0);
+                        },
+                        Some('K') => {
+                            for x in col..self.cols {
+                                self.put(row, x, BLANK);
+                            }
+                        },
+                        Some('J') if params == "2" => self.cells.fill(BLANK),
+                        Some('m') => match params {
+                            "" | "0" => style.clear(),
+                            _ => {
+                                let params = params.strip_prefix("0;").map_or(params, |p| {
+                                    style.clear();
+                                    p
+                                });
+                                if !style.is_empty() {
+                                    style.push(';');
+                                }
+                                style.push_str(params);
+                            },
+                        },
+                        Some('h') if params == "?25" => self.cursor = Some((row, col)),
+                        Some('l') if params == "?25" => self.cursor = None,
+                        _ => (),
+                    }
+                },
+                '\r' => col = 0,
+                '\n' => row += 1,
+                c => {
+                    self.put(row, col, Cell { c, style: style.clone() });
+                    col = col.saturating_add(1);
+                },
+            }
+        }
+    }
+
+    fn put(&mut self, row: u16, col: u16, cell: Cell) {
+        if row < self.rows && col < self.cols {
+            self.cells[row as usize * self.cols as usize + col as usize] = cell;
+        }
+    }
+
+    fn row(&self, row: u16) -> &[Cell] {
+        let start = row as usize * self.cols as usize;
+        &self.cells[start..start + self.cols as usize]
+    }
+
+    // What to send to turn a screen showing `prev` into this frame. Each row
+    // that changed is rewritten from its first to its last changed cell, and
+    // a blank tail is erased instead of written out. Rows with non-ASCII text
+    // are rewritten whole, as wide and combining characters do not take one
+    // column each on the terminal. Without a previous frame of the same size
+    // the screen is cleared and drawn whole.
+    pub fn diff(&self, prev: Option<&Frame>) -> String {
+        let prev = prev.filter(|p| p.cols == self.cols && p.rows == self.rows);
+        let mut out = String::from("\x1b[?25l");
+        if prev.is_none() {
+            out.push_str("\x1b[m\x1b[2J");
+        }
+
+        let mut style = String::new();
+        for row in 0..self.rows {
+            let cells = self.row(row);
+            let old = prev.map_or(&[][..], |p| p.row(row));
+            let changed = |x: &usize| old.get(*x).unwrap_or(&BLANK) != &cells[*x];
+            let Some(first) = (0..cells.len()).find(changed) else {
+                continue;
+            };
+            let last = (0..cells.len()).rfind(changed).unwrap_or(first);
+            let (first, last) = if cells.iter().chain(old).any(|cell| !cell.c.is_ascii()) { (0, cells.len() - 1) } else { (first, last) };
+            let end = cells.iter().rposition(|cell| *cell != BLANK).map_or(0, |x| x + 1);
+
+            out.push_str(&format!("\x1b[{};{}H", row + 1, first + 1));
+            for cell in cells.get(first..(last + 1).min(end)).unwrap_or(&[]) {
+                if cell.style != style {
+                    style = cell.style.clone();
+                    out.push_str(&if style.is_empty() { "\x1b[m".to_string() } else { format!("\x1b[0;{}m", style) });
+                }
+                out.push(cell.c);
+            }
+            if last >= end {
+                if !style.is_empty() {
+                    style.clear();
+                    out.push_str("\x1b[m");
+                }
+                out.push_str("\x1b[K");
+            }
+        }
+
+        out.push_str("\x1b[m");
+        if let Some((row, col)) = self.cursor {
+            out.push_str(&format!("\x1b[{};{}H\x1b[?25h", row + 1, col + 1));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(cols: u16, buf: &str) -> Frame {
+        let mut frame = Frame::new(cols, 2);
+        frame.draw(buf);
+        frame
+    }
+
+    #[test]
+    fn wrap_cuts_at_the_width_without_linebreak() {
+        assert_eq!(wrap_columns("abcdefghij", 4, 0, false), vec![(0, 4), (4, 8), (8, 10)]);
+        assert_eq!(wrap_columns("one two three", 6, 0, false), vec![(0, 6), (6, 12), (12, 13)]);
+        assert_eq!(wrap_columns("abcd", 4, 0, false), vec![(0, 4)]);
+        assert_eq!(wrap_columns("", 4, 0, false), vec![(0, 0)]);
+    }
+
+    #[test]
+    fn wrap_breaks_after_spaces_with_linebreak() {
+        assert_eq!(wrap_columns("one two three", 6, 0, true), vec![(0, 4), (4, 8), (8, 13)]);
+        // A word longer than the width is cut where it has to be
+        assert_eq!(wrap_columns("a verylongword b", 6, 0, true), vec![(0, 2), (2, 8), (8, 14), (14, 16)]);
+    }
+
+    #[test]
+    fn wrap_leaves_room_for_the_hanging_indent() {
+        assert_eq!(wrap_columns("- one two three four", 10, 2, true), vec![(0, 10), (10, 16), (16, 20)]);
+        assert_eq!(hanging_indent(2, 10), 2);
+        // Too wide an indent is dropped rather than squeezing the text
+        assert_eq!(hanging_indent(4, 10), 0);
+        assert_eq!(wrap_columns("    - one two three", 10, 4, false), vec![(0, 10), (10, 19)]);
+    }
+
+    #[test]
+    fn frames_drop_what_falls_outside_them() {
+        let mut tiny = Frame::new(2, 1);
+        tiny.draw("\x1b[1;1Habc\x1b[5;9Hx\x1b[1;2H\x1b[?25h");
+        assert_eq!(tiny.diff(None), "\x1b[?25l\x1b[m\x1b[2J\x1b[1;1Hab\x1b[m\x1b[1;2H\x1b[?25h");
+
+        let mut empty = Frame::new(0, 0);
+        empty.draw("\x1b[Hxyz");
+        assert_eq!(empty.diff(None), "\x1b[?25l\x1b[m\x1b[2J\x1b[m");
+    }
+
+    #[test]
+    fn preview_starts_with_the_window_it_follows() {
+        assert_eq!(preview_top(3, 5, 10, |_| 1), 3);
+        assert_eq!(preview_top(0, 0, 10, |_| 0), 0);
+    }
+
+    #[test]
+    fn preview_scrolls_on_to_keep_the_cursor_line() {
+        // Lines 2 to 5 wrap to 3 rows each, so only three fit in 9 rows
+        assert_eq!(preview_top(2, 5, 9, |_| 3), 3);
+        assert_eq!(preview_top(2, 5, 8, |_| 3), 4);
+        // The cursor line stays on top even when it alone is taller
+        assert_eq!(preview_top(2, 5, 2, |l| if l == 5 { 4 } else { 1 }), 5);
+        // A cursor above the window pulls the preview up to it
+        assert_eq!(preview_top(6, 4, 9, |_| 1), 4);
+    }
+
+    #[test]
+    fn unchanged_frame_only_places_the_cursor() {
+        let prev = frame(6, "\x1b[Habc\x1b[1;2H\x1b[?25h");
+        let next = frame(6, "\x1b[Habc\x1b[1;2H\x1b[?25h");
+        assert_eq!(next.diff(Some(&prev)), "\x1b[?25l\x1b[m\x1b[1;2H\x1b[?25h");
+    }
+
+    #[test]
+    fn one_changed_cell_is_written_alone() {
+        let prev = frame(6, "\x1b[Habc\r\nxyz");
+        let next = frame(6, "\x1b[Habd\r\nxyz");
+        assert_eq!(next.diff(Some(&prev)), "\x1b[?25l\x1b[1;3Hd\x1b[m");
+    }
+
+    #[test]
+    fn blank_tail_is_erased() {
+        let prev = frame(6, "\x1b[Habcdef");
+        let next = frame(6, "\x1b[Habc");
+        assert_eq!(next.diff(Some(&prev)), "\x1b[?25l\x1b[1;4H\x1b[K\x1b[m");
+    }
+
+    #[test]
+    fn style_change_rewrites_the_cell() {
+        let prev = frame(6, "\x1b[Hab");
+        let next = frame(6, "\x1b[Ha\x1b[1mb\x1b[m");
+        assert_eq!(next.diff(Some(&prev)), "\x1b[?25l\x1b[1;2H\x1b[0;1mb\x1b[m");
+    }
+
+    #[test]
+    fn size_change_clears_and_draws_whole() {
+        let prev = frame(6, "\x1b[Habc");
+        let next = frame(4, "\x1b[Habc");
+        assert_eq!(next.diff(Some(&prev)), "\x1b[?25l\x1b[m\x1b[2J\x1b[1;1Habc\x1b[m");
+    }
+
+    #[test]
+    fn rows_with_wide_text_are_rewritten_whole() {
+        let prev = frame(6, "\x1b[Ha漢b");
+        let next = frame(6, "\x1b[Ha漢c");
+        assert_eq!(next.diff(Some(&prev)), "\x1b[?25l\x1b[1;1Ha漢c\x1b[K\x1b[m");
+    }
+}
@@ -0,0 +1,394 @@
+use crate::terminal::Line;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    None,
+    Left,
+    Center,
+    Right,
+}
+
+// A markdown table. `rows[0]` is the header, the rest is the body.
+pub struct Table {
+    pub indent: String,
+    pub align: Vec<Align>,
+    pub rows: Vec<Vec<String>>,
+    sep_line: Option<usize>,
+}
+
+pub fn is_table_row(line: &str) -> bool {
+    line.trim_start().starts_with('|')
+}
+
+// Returns the [start, end) range of the table block containing `idx`.
+pub fn table_bounds(rows: &[Line], idx: usize) -> Option<(usize, usize)> {
+    if idx >= rows.len() || !is_table_row(&rows[idx].row) {
+        return None;
+    }
+
+    let mut start = idx;
+    while start > 0 && is_table_row(&rows[start - 1].row) {
+        start -= 1;
+    }
+
+    let mut end = idx + 1;
+    while end < rows.len() && is_table_row(&rows[end].row) {
+        end += 1;
+    }
+
+    Some((start, end))
+}
+
+pub fn split_cells(line: &str) -> Vec<String> {
+    let mut line = line.trim();
+    line = line.strip_prefix('|').unwrap_or(line);
+    if line.ends_with('|') && !line.ends_with("\\|") {
+        line = &line[..line.len() - 1];
+    }
+
+    let mut cells = Vec::new();
+    let mut cell = String::new();
+    let mut escaped = false;
+    for c in line.chars() {
+        if c == '|' && !escaped {
+            cells.push(cell.trim().to_string());
+            cell.clear();
+        } else {
+            cell.push(c);
+        }
+        escaped = c == '\\' && !escaped;
+    }
+    cells.push(cell.trim().to_string());
+
+    cells
+}
+
+fn is_separator(cells: &[String]) -> bool {
+    cells.iter().all(|cell| {
+        let dashes = cell.trim_start_matches(':').trim_end_matches(':');
+        !dashes.is_empty() && dashes.chars().all(|c| c == '-')
+    })
+}
+
+fn parse_align(cell: &str) -> Align {
+    match (cell.starts_with(':'), cell.ends_with(':')) {
+        (true, true) => Align::Center,
+        (true, false) => Align::Left,
+        (false, true) => Align::Right,
+        (false, false) => Align::None,
+    }
+}
+
+// Index of the cell the byte column `col` falls into.
+pub fn cell_at(line: &str, col: usize) -> usize {
+    let trimmed = line.trim_start();
+    let indent = line.len() - trimmed.len();
+    if col <= indent {
+        return 0;
+    }
+
+    let mut cell = 0;
+    let mut escaped = false;
+    for (i, c) in trimmed.char_indices() {
+        if indent + i >= col {
+            break;
+        }
+        if c == '|' && !escaped && i != 0 {
+            cell += 1;
+        }
+        escaped = c == '\\' && !escaped;
+    }
+
+    cell
+}
+
+// Byte column of the first content character of cell `idx`.
+pub fn cell_col(line: &str, idx: usize) -> usize {
+    let mut cell = 0;
+    let mut escaped = false;
+    let mut seen_first = false;
+    for (i, c) in line.char_indices() {
+        if c == '|' && !escaped {
+            if !seen_first {
+                seen_first = true;
+            } else {
+                cell += 1;
+            }
+            if cell == idx {
+                let rest = &line[i + 1..];
+                return i + 1 + (rest.len() - rest.trim_start().len()).min(1);
+            }
+        }
+        escaped = c == '\\' && !escaped;
+    }
+
+    line.len()
+}
+
+impl Table {
+    pub fn parse(lines: &[String]) -> Table {
+        let indent = lines.first().map(|l| l[..l.len() - l.trim_start().len()].to_string()).unwrap_or_default();
+        let mut rows = Vec::new();
+        let mut align = Vec::new();
+        let mut sep_line = None;
+
+        for (i, line) in lines.iter().enumerate() {
+            let cells = split_cells(line);
+            if i == 1 && is_separator(&cells) {
+                align = cells.iter().map(|c| parse_align(c)).collect();
+                sep_line = Some(i);
+            } else {
+                rows.push(cells);
+            }
+        }
+
+        let mut table = Table { indent, align, rows, sep_line };
+        table.normalize();
+        table
+    }
+
+    pub fn from_csv(lines: &[String]) -> Table {
+        let rows = lines.iter().map(|line| split_csv(line)).collect();
+        let mut table = Table { indent: String::new(), align: Vec::new(), rows, sep_line: None };
+        table.normalize();
+        table
+    }
+
+    fn normalize(&mut self) {
+        let cols = self.rows.iter().map(|r| r.len()).max().unwrap_or(0).max(self.align.len()).max(1);
+        for row in self.rows.iter_mut() {
+            row.resize(cols, String::new());
+        }
+        if self.rows.is_empty() {
+            self.rows.push(vec![String::new(); cols]);
+        }
+        self.align.resize(cols, Align::None);
+    }
+
+    pub fn columns(&self) -> usize {
+        self.align.len()
+    }
+
+    // Maps a line of the original block to a row of the table.
+    pub fn row_of_line(&self, line: usize) -> usize {
+        match self.sep_line {
+            Some(sep) if line >= sep => line - 1,
+            _ => line,
+        }
+    }
+
+    // Maps a row of the table to its line in the formatted output.
+    pub fn line_of_row(row: usize) -> usize {
+        if row == 0 { 0 } else { row + 1 }
+    }
+
+    pub fn insert_row(&mut self, at: usize) {
+        let at = at.clamp(1, self.rows.len());
+        self.rows.insert(at, vec![String::new(); self.columns()]);
+    }
+
+    pub fn add_column(&mut self, at: usize) {
+        let at = at.min(self.columns());
+        for row in self.rows.iter_mut() {
+            row.insert(at, String::new());
+        }
+        self.align.insert(at, Align::None);
+    }
+
+    pub fn delete_column(&mut self, at: usize) {
+        if self.columns() <= 1 || at >= self.columns() {
+            return;
+        }
+        for row in self.rows.iter_mut() {
+            row.remove(at);
+        }
+        self.align.remove(at);
+    }
+
+    pub fn sort(&mut self, col: usize) {
+        if col >= self.columns() || self.rows.len() < 2 {
+            return;
+        }
+
+        // Numbers first in numeric order, then text ignoring case
+        let key = |cell: &str| match cell.parse::<f64>() {
+            Ok(n) if n.is_finite() => (false, n, String::new()),
+            _ => (true, 0.0, cell.to_lowercase()),
+        };
+        self.rows[1..].sort_by(|a, b| {
+            let (a, b) = (key(&a[col]), key(&b[col]));
+            a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)).then(a.2.cmp(&b.2))
+        });
+    }
+
+    pub fn format(&self) -> Vec<String> {
+        let mut widths = vec![3; self.columns()];
+        for row in self.rows.iter() {
+            for (i, cell) in row.iter().enumerate() {
+                widths[i] = widths[i].max(cell.chars().count());
+            }
+        }
+
+        let mut lines = Vec::new();
+        for (r, row) in self.rows.iter().enumerate() {
+            let cells: Vec<String> = row.iter().enumerate().map(|(i, cell)| pad(cell, widths[i], self.align[i])).collect();
+            lines.push(format!("{}| {} |", self.indent, cells.join(" | ")));
+
+            if r == 0 {
+                let seps: Vec<String> = widths.iter().zip(self.align.iter()).map(|(w, a)| match a {
+                    Align::None => "-".repeat(*w),
+                    Align::Left => format!(":{}", "-".repeat(w - 1)),
+                    Align::Center => format!(":{}:", "-".repeat(w - 2)),
+                    Align::Right => format!("{}:", "-".repeat(w - 1)),
+                }).collect();
+                lines.push(format!("{}| {} |", self.indent, seps.join(" | ")));
+            }
+        }
+
+        lines
+    }
+}
+
+fn pad(cell: &str, width: usize, align: Align) -> String {
+    let fill = width - cell.chars().count();
+    match align {
+        Align::Right => format!("{}{}", " ".repeat(fill), cell),
+        Align::Center => format!("{}{}{}", " ".repeat(fill / 2), cell, " ".repeat(fill - fill / 2)),
+        _ => format!("{}{}", cell, " ".repeat(fill)),
+    }
+}
+
+fn split_csv(line: &str) -> Vec<String> {
+    let mut cells = Vec::new();
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                cell.push('"');
+                chars.next();
+            },
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                cells.push(cell.trim().replace('|', "\\|"));
+                cell.clear();
+            },
+            _ => cell.push(c),
+        }
+    }
+    cells.push(cell.trim().replace('|', "\\|"));
+
+    cells
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &[&str]) -> Vec<String> {
+        text.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn split_cells_keeps_escaped_pipes() {
+        assert_eq!(split_cells("| a | b \\| c |"), vec!["a", "b \\| c"]);
+        assert_eq!(split_cells("  |x|y"), vec!["x", "y"]);
+    }
+
+    #[test]
+    fn parse_reads_alignment_and_pads_short_rows() {
+        let table = Table::parse(&lines(&["| a | b | c |", "|:--|:-:|--:|", "| 1 |"]));
+        assert_eq!(table.columns(), 3);
+        assert!(table.align == vec![Align::Left, Align::Center, Align::Right]);
+        assert_eq!(table.rows, vec![vec!["a", "b", "c"], vec!["1", "", ""]]);
+        assert_eq!(table.row_of_line(2), 1);
+    }
+
+    #[test]
+    fn format_lines_up_pipes() {
+        let table = Table::parse(&lines(&["  |name|n|", "|-|--:|", "|apple|10|"]));
+        assert_eq!(table.format(), lines(&[
+            "  | name  |   n |",
+            "  | ----- | --: |",
+            "  | apple |  10 |",
+        ]));
+    }
+
+    #[test]
+    fn format_adds_a_separator_when_missing() {
+        let table = Table::parse(&lines(&["| a | b |", "| 1 | 2 |"]));
+        assert_eq!(table.format()[1], "| --- | --- |");
+        assert_eq!(table.format().len(), 3);
+    }
+
+    #[test]
+    fn sort_keeps_the_header_and_compares_numbers_as_numbers() {
+        let mut table = Table::parse(&lines(&["| n |", "|---|", "| b |", "| C |", "| a |"]));
+        table.sort(0);
+        let col: Vec<&str> = table.rows.iter().map(|row| row[0].as_str()).collect();
+        assert_eq!(col, vec!["n", "a", "b", "C"]);
+
+        let mut table = Table::parse(&lines(&["| n |", "|---|", "| 10 |", "| 9 |", "| 2.5 |"]));
+        table.sort(0);
+        let col: Vec<&str> = table.rows.iter().map(|row| row[0].as_str()).collect();
+        assert_eq!(col, vec!["n", "2.5", "9", "10"]);
+    }
+
+    #[test]
+    fn sort_puts_numbers_before_text_in_mixed_columns() {
+        let mut rows = vec!["| n |".to_string(), "|---|".to_string()];
+        for i in 0..10 {
+            rows.extend([format!("| {} |", i * 7 % 10), format!("| {}a |", i), "| NaN |".to_string(), format!("| x{} |", i)]);
+        }
+        let mut table = Table::parse(&rows);
+        table.sort(0);
+        let col: Vec<&str> = table.rows[1..].iter().map(|row| row[0].as_str()).collect();
+        assert_eq!(&col[..10], &["0", "1", "2", "3", "4", "5", "6", "7", "8", "9"]);
+        assert_eq!(&col[10..13], &["0a", "1a", "2a"]);
+        assert_eq!(&col[20..30], &["NaN"; 10]);
+        assert_eq!(col[39], "x9");
+    }
+
+    #[test]
+    fn columns_are_added_and_deleted() {
+        let mut table = Table::parse(&lines(&["| a | b |", "|---|--:|", "| 1 | 2 |"]));
+        table.add_column(1);
+        assert_eq!(table.rows[1], vec!["1", "", "2"]);
+        assert!(table.align[2] == Align::Right);
+
+        table.delete_column(0);
+        table.delete_column(5);
+        assert_eq!(table.rows[0], vec!["", "b"]);
+
+        table.delete_column(0);
+        table.delete_column(0);
+        assert_eq!(table.columns(), 1);
+    }
+
+    #[test]
+    fn insert_row_never_goes_above_the_header() {
+        let mut table = Table::parse(&lines(&["| a |", "|---|", "| 1 |"]));
+        table.insert_row(0);
+        assert_eq!(table.rows, vec![vec!["a"], vec![""], vec!["1"]]);
+        assert_eq!(Table::line_of_row(1), 2);
+    }
+
+    #[test]
+    fn split_csv_handles_quotes_and_pipes() {
+        assert_eq!(split_csv(r#"a, "b, c" ,"say ""hi""",x|y"#), vec!["a", "b, c", "say \"hi\"", "x\\|y"]);
+        let table = Table::from_csv(&lines(&["a,b", "1"]));
+        assert_eq!(table.rows, vec![vec!["a", "b"], vec!["1", ""]]);
+    }
+
+    #[test]
+    fn cells_are_found_by_column() {
+        let line = "| ab | cd |";
+        assert_eq!(cell_at(line, 0), 0);
+        assert_eq!(cell_at(line, 3), 0);
+        assert_eq!(cell_at(line, 6), 1);
+        assert_eq!(cell_col(line, 0), 2);
+        assert_eq!(cell_col(line, 1), 7);
+    }
+}
@@ -1,25 +1,41 @@
-use std::fmt::format;
+use std::collections::HashMap;
+use std::env;
 use std::io;
 use std::io::Write;
-use std::process::exit;
+use std::process::{exit, Command};
 use std::error::Error;
 use std::fs::File;
 use std::fs;
 use std::io::BufRead;
+use std::path::{Path, PathBuf};
 
 use crossterm::terminal::{enable_raw_mode, size};
+use regex::Regex;
 
+use crate::date::{days_in_month, seconds_today, Date};
+use crate::export::{default_output, export_html, slug};
 use crate::input::{Input, Keys};
-use crate::markdown::parse_line_to_markdown;
-use crate::screen::clear_screen;
+use crate::finder::{Entry, Finder};
+use crate::list::{truncate, ListItem, ListKind, ListView};
+use crate::notes::{backlinks, expand_template, journal_date, journal_path, rewrite_links, link_at, note_title, resolve_file, resolve_wiki, template, template_fields, write_batch, Link, TemplateContext};
+use crate::markdown::{code_lines, front_matter_len, tags, render_meta_line, headings, is_setext_underline, list_prefix, parse_lines_to_markdown, reflow, render_code_line, render_line, section_at, section_end, shift_section, sibling_swap, renumber, task_state, toggle_task, Conceal};
+use crate::screen::{clear_screen, hanging_indent, preview_top, slice_columns, Frame, strip_escapes, visible_width, wrap_columns};
+use crate::workspace::{discover, files_matching, matches_query, note_files, project_files, query_words, read_title, snippet, title, Index};
+use crate::table::{cell_at, cell_col, table_bounds, Table};
+use crate::window::{neighbour, tab_line, Layout, Rect};
 
 #[derive(PartialEq, Eq)]
 enum Mode {
     Normal,
     Insert,
     Command,
+    List,
+    Outline,
+    Prompt,
+    Find,
 }
 
+#[derive(Clone, Copy)]
 struct Cursor {
     cx: u16,
     cy: u16
@@ -28,10 +44,189 @@ struct Cursor {
 pub struct Line {
     pub row: String,
     pub render: String,
+    // Display column of every byte of `row` within `render`.
+    pub cols: Vec<usize>,
+    // Set on a heading whose section is folded away.
+    pub folded: bool,
+    // Set on lines inside a fenced code block.
+    pub code: bool,
+    // Set on lines of the front matter block.
+    pub meta: bool,
+    // What `render` was made from, so an unchanged line is not rendered again.
+    rendered: Option<(String, bool, bool, Conceal)>,
+}
+
+impl Line {
+    pub fn new(row: String) -> Line {
+        Line { row, render: String::new(), cols: vec![0], folded: false, code: false, meta: false, rendered: None }
+    }
+}
+
+// A document that is open but not shown, kept so links can come back to it.
+struct Buffer {
+    id: usize,
+    rows: Vec<Line>,
+    num_rows: u16,
+    filename: Option<String>,
+    dirty: u32,
+    cursor: Cursor,
+    rowoffset: u16,
+    coloffset: u16,
+}
+
+// A window that is not focused, with its own place in the buffer it shows.
+struct Window {
+    id: usize,
+    buffer: usize,
+    cursor: Cursor,
+    rowoffset: u16,
+    coloffset: u16,
+    // Set on a preview, to the window it follows.
+    preview: Option<usize>,
+}
+
+// A linewise motion typed after an operator, as far as it has been typed.
+enum Motion {
+    // More keys may complete it.
+    Pending,
+    // The lines it covers, end exclusive.
+    Lines(usize, usize),
+    Invalid,
+}
+
+// The text of a line split into display rows, with the hanging indent of
+// the rows after the first.
+type Wrapped = (String, Vec<(usize, usize)>, usize);
+
+// What the loaded window shows this frame: the lines inside closed folds,
+// the display rows of each wrapped line once asked for, and how many display
+// rows of the top line are scrolled off when it is taller than the window.
+struct Visible {
+    hidden: Vec<bool>,
+    wraps: HashMap<usize, Wrapped>,
+    skip: usize,
+}
+
+// A tab page that is not shown, with the window that was focused in it.
+struct Tab {
+    layout: Layout,
+    window: usize,
+}
+
+// A note waiting for the custom fields of its template to be filled in.
+struct NewNote {
+    path: PathBuf,
+    template: String,
+    context: TemplateContext,
+    fields: Vec<String>,
+    values: Vec<(String, String)>,
+}
+
+// A note rename and the link rewrites that go with it, kept so it can be undone.
+struct Rename {
+    from: PathBuf,
+    to: PathBuf,
+    // Notes whose links change, with their text before and after.
+    files: Vec<(PathBuf, String, String)>,
+}
+
+pub fn read_lines(filename: &str) -> Result<Vec<Line>, Box<dyn Error>> {
+    let file = File::open(filename)?;
+    let reader = io::BufReader::new(file);
+
+    let mut rows = Vec::new();
+    for line in reader.lines() {
+        rows.push(Line::new(line?));
+    }
+    parse_lines_to_markdown(&mut rows, Conceal::Full);
+
+    Ok(rows)
+}
+
+// `path` relative to the working directory when it is below it.
+fn display_path(path: &Path) -> String {
+    let cwd = env::current_dir().unwrap_or_default();
+    path.strip_prefix(&cwd).or_else(|_| path.strip_prefix(".")).unwrap_or(path).to_string_lossy().to_string()
+}
+
+// `:s/pattern/replacement/` with any punctuation as the delimiter.
+fn is_substitute(command: &str) -> bool {
+    command.strip_prefix(":s").and_then(|rest| rest.chars().next()).is_some_and(|c| c.is_ascii_punctuation() && c != '_')
+}
+
+// Splits `text` on `delimiter`, keeping escaped delimiters as plain characters.
+fn split_unescaped(text: &str, delimiter: char) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\' && chars.peek() == Some(&delimiter) {
+            parts.last_mut().unwrap().push(delimiter);
+            chars.next();
+        } else if c == delimiter {
+            parts.push(String::new());
+        } else {
+            parts.last_mut().unwrap().push(c);
+        }
+    }
+    parts
+}
+
+// How `line` is drawn at the given conceal level.
+fn render_text(line: &Line, conceal: Conceal) -> (String, Vec<usize>) {
+    if line.meta {
+        render_meta_line(&line.row, conceal)
+    } else if line.code {
+        render_code_line(&line.row, conceal)
+    } else {
+        render_line(&line.row, conceal)
+    }
+}
+
+// The display row of a wrapped line that holds column `col`.
+fn segment_at(segments: &[(usize, usize)], col: usize) -> usize {
+    segments.iter().rposition(|(start, _)| *start <= col).unwrap_or(0)
+}
+
+// Where a mark on `line` ends up when `removed` lines at `at` are replaced by
+// `added` ones, None when its line is deleted outright.
+fn shifted_line(line: usize, at: usize, removed: usize, added: usize) -> Option<usize> {
+    if line < at {
+        Some(line)
+    } else if line >= at + removed {
+        Some(line + added - removed)
+    } else if added == 0 {
+        None
+    } else {
+        Some(at + (line - at).min(added - 1))
+    }
+}
+
+// The gutter signs for the hunks of `git diff -U0` output: `+` on added
+// lines, `~` on changed ones and `_` under lines that were deleted.
+fn diff_signs(diff: &str) -> Vec<(usize, char)> {
+    let hunk = Regex::new(r"^@@ -\d+(?:,(\d+))? \+(\d+)(?:,(\d+))? @@").unwrap();
+    let mut changes = Vec::new();
+    for caps in diff.lines().filter_map(|line| hunk.captures(line)) {
+        let count = |i: usize| caps.get(i).and_then(|c| c.as_str().parse::<usize>().ok()).unwrap_or(1);
+        let (removed, start, added) = (count(1), count(2), count(3));
+        if added == 0 {
+            changes.push((start.saturating_sub(1), '_'));
+        } else {
+            let sign = if removed == 0 { '+' } else { '~' };
+            changes.extend((start - 1..start - 1 + added).map(|line| (line, sign)));
+        }
+    }
+    changes
+}
+
+fn same_file(a: &str, b: &str) -> bool {
+    a == b || matches!((fs::canonicalize(a), fs::canonicalize(b)), (Ok(a), Ok(b)) if a == b)
 }
 
 pub struct Terminal {
     term_buf: String,
+    // The frame on screen, to draw the next one against.
+    frame: Option<Frame>,
     size: (u16, u16),
     cursor: Cursor, 
     input: Input,
@@ -43,6 +238,59 @@ pub struct Terminal {
     status: String,
     mode: Mode,
     dirty: u32,
+    list: Option<ListView>,
+    outline: Option<ListView>,
+    backlinks: Option<ListView>,
+    conceal: Conceal,
+    pending: String,
+    register: Vec<String>,
+    buffer_id: usize,
+    next_buffer_id: usize,
+    buffers: Vec<Buffer>,
+    jumps: Vec<(usize, Cursor)>,
+    window_id: usize,
+    next_window_id: usize,
+    windows: Vec<Window>,
+    layout: Layout,
+    // Screen area of the window being drawn, the focused one between frames.
+    view: Rect,
+    focus: usize,
+    preview: Option<usize>,
+    tabs: Vec<Tab>,
+    // Position of the shown tab page among all of them.
+    tab: usize,
+    number: bool,
+    relativenumber: bool,
+    wrap: bool,
+    linebreak: bool,
+    breakindent: bool,
+    // Column to wrap at while typing, off when 0.
+    textwidth: usize,
+    // Ctrl-W starts a window command instead of saving.
+    winkeys: bool,
+    // Forced on or off, otherwise shown when the buffer has signs.
+    signcolumn: Option<bool>,
+    // Signs of the loaded buffer by line, with their colour.
+    signs: HashMap<usize, (char, &'static str)>,
+    git_changes: HashMap<String, Vec<(usize, char)>>,
+    search: Option<Regex>,
+    diagnostics: Vec<ListItem>,
+    // Bookmarks as buffer, letter and line.
+    marks: Vec<(usize, char, usize)>,
+    notes_root: Option<PathBuf>,
+    index: Option<Index>,
+    rename: Option<Rename>,
+    last_rename: Option<Rename>,
+    new_note: Option<NewNote>,
+    finder: Option<(PathBuf, Finder)>,
+    quickfix: Vec<ListItem>,
+    quickfix_idx: usize,
+}
+
+impl Default for Terminal {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Terminal {
@@ -59,12 +307,12 @@ impl Terminal {
         let size = size().unwrap();
 
 
-        Terminal { term_buf: String::new(), size: (size.0, size.1 - 2) , cursor: Cursor { cx: 0, cy: 0 }, input: Input {}, num_rows:0, rows: Vec::new(), rowoffset: 0, coloffset: 0, filename: None, status: ":help Ctrl+Q to quit".to_string(), mode: Mode::Normal, dirty: 0}
+        Terminal { term_buf: String::new(), frame: None, size: (size.0, size.1.saturating_sub(2)) , cursor: Cursor { cx: 0, cy: 0 }, input: Input {}, num_rows:0, rows: Vec::new(), rowoffset: 0, coloffset: 0, filename: None, status: ":help Ctrl+Q to quit".to_string(), mode: Mode::Normal, dirty: 0, list: None, outline: None, backlinks: None, conceal: Conceal::Full, pending: String::new(), register: Vec::new(), buffer_id: 0, next_buffer_id: 1, buffers: Vec::new(), jumps: Vec::new(), window_id: 0, next_window_id: 1, windows: Vec::new(), layout: Layout::Window(0), view: Rect { x: 0, y: 0, w: size.0, h: size.1.saturating_sub(1) }, focus: 0, preview: None, tabs: Vec::new(), tab: 0, number: false, relativenumber: false, wrap: false, linebreak: false, breakindent: false, textwidth: 0, winkeys: false, signcolumn: None, signs: HashMap::new(), git_changes: HashMap::new(), search: None, diagnostics: Vec::new(), marks: Vec::new(), notes_root: None, index: None, rename: None, last_rename: None, new_note: None, finder: None, quickfix: Vec::new(), quickfix_idx: 0}
        
     }
 
     pub fn open_empty_editor(&mut self) {
-        self.rows = vec![Line{row:"".to_string(), render: "".to_string()}];
+        self.rows = vec![Line::new(String::new())];
         self.num_rows = 0;
         self.filename = None;
     }
@@ -81,6 +329,9 @@ impl Terminal {
         }
 
         self.num_rows = self.rows.len() as u16;
+        if let Some(filename) = self.filename.clone() {
+            self.load_git_changes(&filename);
+        }
 
         Ok(())
     }
@@ -89,10 +340,18 @@ impl Terminal {
         let contents = self.convert_rows_to_str();
 
         if let Some(filename) = &self.filename {
-            match fs::write(&filename, contents) {
+            // New notes may live in folders that do not exist yet
+            if let Some(dir) = Path::new(filename).parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                let _ = fs::create_dir_all(dir);
+            }
+            match fs::write(filename, contents) {
                 Ok(()) => {
                     self.status = format!("Successfully written {} lines to {}", self.rows.len(), filename);
                     self.dirty = 0;
+                    let filename = filename.clone();
+                    self.load_git_changes(&filename);
+                    self.index_file(&PathBuf::from(filename));
+                    self.update_backlinks();
                 },
                 Err(err) => self.status = err.to_string()
             }
@@ -104,65 +363,95 @@ impl Terminal {
     }
 
     fn convert_rows_to_str(&mut self) -> String {
-        return self.rows.iter().map(|line| &line.row as &str).collect::<Vec<&str>>().join("\n");
+        self.rows.iter().map(|line| &line.row as &str).collect::<Vec<&str>>().join("\n")
     }
 
     fn append_line(&mut self, row: String) {
-        let NUMTABS = 4;
-        let mut render = String::new();
-        for c in row.chars() {
-            if c == '\t' {
-                render.push_str(&" ".repeat(NUMTABS));
-            } else {
-                render.push(c);
-            }
-        }
-
-        let markdown = parse_line_to_markdown(render);
-        let line = Line { row, render: markdown };
-        self.rows.push(line);
+        self.rows.push(Line::new(row));
+        self.update_line(self.rows.len() - 1);
     }
 
     fn update_line(&mut self, idx: usize) {
-        let NUMTABS = 4;
-        let row = &self.rows[idx].row;
-        let mut render = String::new();
-        for c in row.chars() {
-            if c == '\t' {
-                render.push_str(&" ".repeat(NUMTABS));
-            } else {
-                render.push(c);
-            }
+        let line = &self.rows[idx];
+        if line.rendered.as_ref().is_some_and(|(row, code, meta, conceal)| *row == line.row && *code == line.code && *meta == line.meta && *conceal == self.conceal) {
+            return;
         }
 
-        let line = Line { row: row.to_string(), render: parse_line_to_markdown(render) };
-        self.rows[idx] = line;
+        let (render, cols) = render_text(line, self.conceal);
+        let line = &mut self.rows[idx];
+        line.render = render;
+        line.cols = cols;
+        line.rendered = Some((line.row.clone(), line.code, line.meta, self.conceal));
+    }
 
-    } 
+    // Re-renders the lines that moved in or out of a code block or the front
+    // matter since the last frame.
+    fn update_code_blocks(&mut self) {
+        let meta = front_matter_len(&self.rows);
+        for (idx, code) in code_lines(&self.rows).into_iter().enumerate() {
+            if self.rows[idx].code != code || self.rows[idx].meta != (idx < meta) {
+                self.rows[idx].code = code;
+                self.rows[idx].meta = idx < meta;
+                self.update_line(idx);
+            }
+        }
+    }
 
     fn refresh_screen(&mut self) {
-        self.editor_scroll();
-
         self.term_buf.clear();
         self.term_buf.push_str("\x1b[?25l");
         self.term_buf.push_str("\x1b[H");
 
-        self.render_row('~');
+        // Every window is drawn with its own buffer and place loaded
+        let area = self.layout_area();
+        self.focus = self.window_id;
+        self.sync_previews();
+        let mut focus_visible = None;
+        for (id, rect) in self.layout.rects(area) {
+            self.load_window(id);
+            self.view = rect;
+            self.update_code_blocks();
+            self.update_signs();
+            // Folds and wrapping are worked out once per window each frame
+            let mut visible = self.visible();
+            self.editor_scroll(&mut visible);
+            self.draw_window(area, &mut visible);
+            if id == self.focus {
+                focus_visible = Some(visible);
+            }
+        }
+        self.load_window(self.focus);
+        self.update_signs();
+        self.view = self.layout.rects(area).into_iter().find(|(id, _)| *id == self.focus).map_or(area, |(_, rect)| rect);
 
-        self.draw_status_bar(); 
-        self.draw_message_bar(); 
+        self.update_outline();
+        self.draw_tab_line();
+        self.draw_outline(area);
+        self.draw_list();
+        self.draw_message_bar();
 
         // execute!(io::stdout(), MoveTo(self.cursor.cx, self.cursor.cy)).unwrap();
-        self.term_buf.push_str(& format!("\x1b[{};{}H", (self.cursor.cx - self.rowoffset) + 1, (self.cursor.cy - self.coloffset) + 1));
+        let mut visible = focus_visible.unwrap_or_else(|| self.visible());
+        let (row, col) = self.cursor_screen(&mut visible);
+        self.term_buf.push_str(& format!("\x1b[{};{}H", self.view.y + row + 1, self.view.x + col + 1));
         
         // Comment to not restore cursor to 0,0
         // self.term_buf.push_str("\x1b[H");
-        
+
+        // The finder draws over everything and takes the cursor
+        if self.finder.is_some() {
+            self.draw_finder();
+        }
+
         self.term_buf.push_str("\x1b[?25h");
     }
 
-    fn draw_screen(&self) {
-        print!("{}", self.term_buf);
+    // Sends the frame in `term_buf`, or just what changed since the last one.
+    fn draw_screen(&mut self) {
+        let mut frame = Frame::new(self.size.0, self.size.1 + 2);
+        frame.draw(&self.term_buf);
+        print!("{}", frame.diff(self.frame.as_ref()));
+        self.frame = Some(frame);
     }
 
     pub fn run(self: &mut Terminal) {
@@ -172,8 +461,10 @@ impl Terminal {
         loop {
             // clear_screen();
  
-            let key = self.input.handle_input();
-            self.handle_input(key);
+            match self.input.handle_input() {
+                Keys::Resize(cols, rows) => self.resize(cols, rows),
+                key => self.handle_input(key),
+            }
 
             self.refresh_screen();
             // render_row('~', self.size.1);
@@ -183,63 +474,358 @@ impl Terminal {
         }
     }
 
-    fn editor_scroll(&mut self) {
+    // Takes on the new terminal size. Windows, panels and scroll offsets are
+    // worked out from it on the next frame.
+    fn resize(&mut self, cols: u16, rows: u16) {
+        self.size = (cols, rows.saturating_sub(2));
+        self.view = self.layout_area();
+        self.frame = None;
+    }
+
+    // Screen left for the windows once the tab line and panels have taken
+    // their share.
+    fn layout_area(&self) -> Rect {
+        let rows = match self.list.as_ref().or(self.backlinks.as_ref()) {
+            Some(list) => self.size.1 - list.height(self.size.1 / 2),
+            None => self.size.1,
+        };
+        let top = if self.tabs.is_empty() { 0 } else { 1 };
+        Rect { x: 0, y: top, w: self.size.0.saturating_sub(self.outline_width()), h: (rows + 1).saturating_sub(top) }
+    }
+
+    // Rows of text in the window, above its status line.
+    fn text_rows(&self) -> u16 {
+        self.view.h.saturating_sub(1)
+    }
+
+    fn text_cols(&self) -> u16 {
+        self.view.w.saturating_sub(self.gutter_width())
+    }
+
+    // Columns left of the text for the sign column and line numbers.
+    fn gutter_width(&self) -> u16 {
+        if self.preview.is_some() {
+            return 0;
+        }
+        let numbers = if self.number || self.relativenumber { self.num_rows.max(1).to_string().len().max(3) as u16 + 1 } else { 0 };
+        let signs = if self.signcolumn.unwrap_or(!self.signs.is_empty()) { 2 } else { 0 };
+        numbers + signs
+    }
+
+    fn gutter(&self, filerow: usize) -> String {
+        let mut out = String::new();
+        if self.signcolumn.unwrap_or(!self.signs.is_empty()) {
+            match self.signs.get(&filerow) {
+                Some((sign, color)) => out.push_str(&format!("\x1b[{}m{}\x1b[m ", color, sign)),
+                None => out.push_str("  "),
+            }
+        }
+
+        if self.number || self.relativenumber {
+            let width = self.num_rows.max(1).to_string().len().max(3);
+            let cx = self.cursor.cx as usize;
+            let number = if filerow != cx && self.relativenumber {
+                format!("{:>width$}", filerow.abs_diff(cx))
+            } else if self.relativenumber && self.number {
+                format!("{:<width$}", filerow + 1)
+            } else if self.relativenumber {
+                format!("{:>width$}", 0)
+            } else {
+                format!("{:>width$}", filerow + 1)
+            };
+            let style = if filerow == cx { "\x1b[1m" } else { "\x1b[2m" };
+            out.push_str(&format!("{}{}\x1b[m ", style, number));
+        }
+        out
+    }
+
+    fn outline_width(&self) -> u16 {
+        match self.outline {
+            Some(_) => (self.size.0 / 3).min(32) + 1,
+            None => 0,
+        }
+    }
+
+    fn editor_scroll(&mut self, visible: &mut Visible) {
+        let rows = self.text_rows() as usize;
+        let hidden = &visible.hidden;
+
+        // Never leave the cursor inside a closed fold
+        while self.cursor.cx > 0 && hidden.get(self.cursor.cx as usize) == Some(&true) {
+            self.cursor.cx -= 1;
+        }
 
         if self.cursor.cx < self.rowoffset {
             self.rowoffset = self.cursor.cx;
         }
-        if self.cursor.cx >= self.rowoffset + self.size.1 {
-            self.rowoffset = self.cursor.cx - self.size.1 + 1;
+
+        let cx = self.cursor.cx as usize;
+        if self.wrap {
+            self.coloffset = 0;
+            if cx >= self.num_rows as usize {
+                return;
+            }
+
+            // Drop lines off the top until the cursor row fits, then rows of
+            // the cursor line itself when it is taller than the window
+            let mut top = (self.rowoffset as usize).max(cx.saturating_sub(rows));
+            let heights: Vec<usize> = (top..cx).map(|l| if visible.hidden[l] { 0 } else { self.wrapped(visible, l).1.len() }).collect();
+            let col = self.cursor_col() as usize;
+            let mut used = heights.iter().sum::<usize>() + segment_at(&self.wrapped(visible, cx).1, col) + 1;
+            for height in heights {
+                if used <= rows {
+                    break;
+                }
+                used -= height;
+                top += 1;
+            }
+            self.rowoffset = top as u16;
+            visible.skip = used.saturating_sub(rows);
+            return;
+        }
+
+        let visible = (self.rowoffset as usize..=cx).filter(|l| !hidden.get(*l).copied().unwrap_or(false)).count();
+        if visible > rows {
+            let mut top = cx;
+            let mut shown = 1;
+            while top > 0 && shown < rows {
+                top -= 1;
+                if !hidden[top] {
+                    shown += 1;
+                }
+            }
+            self.rowoffset = top as u16;
         }
 
-        if self.cursor.cy < self.coloffset {
-            self.coloffset = self.cursor.cy;
+        let col = self.cursor_col();
+        if col < self.coloffset {
+            self.coloffset = col;
         }
-        if self.cursor.cy >= self.coloffset + self.size.0 {
-            self.coloffset = self.cursor.cy - self.size.0 + 1;
+        if col >= self.coloffset + self.text_cols() {
+            self.coloffset = col - self.text_cols() + 1;
         }
     }
 
-    fn render_row(&mut self, c: char) {
-        for i in 0..self.size.1 {
-            let filerow = i + self.rowoffset;
-            if filerow >= self.num_rows {
-                let welcome = "Next Version 1.1".to_string();
+    // The cursor line is shown raw while it is being edited, and the whole
+    // window once a preview shows the rendered text beside it.
+    fn shows_raw(&self, filerow: usize) -> bool {
+        (self.mode == Mode::Insert && filerow == self.cursor.cx as usize && self.window_id == self.focus)
+            || self.windows.iter().any(|w| w.preview == Some(self.window_id))
+    }
 
-                if self.num_rows == 0 && i == self.size.1 / 3 {
-                    let mut padding = String::new();
-                    padding.push(c);
-                    for _ in 0..((self.size.0 / 2) - (welcome.len() as u16 / 2)) - 1 {
-                        padding.push(' ');
-                    }
-                    self.term_buf.push_str(&padding);
-                    self.term_buf.push_str(&welcome);
+    // Screen column of the cursor, accounting for concealed markup.
+    fn cursor_col(&self) -> u16 {
+        let cx = self.cursor.cx as usize;
+        let cy = self.cursor.cy as usize;
+        if cx >= self.rows.len() {
+            return 0;
+        }
+
+        let line = &self.rows[cx];
+        let cols = if self.shows_raw(cx) { render_text(line, Conceal::Raw).1 } else { line.cols.clone() };
+        cols.get(cy).or(cols.last()).copied().unwrap_or(0) as u16
+    }
+
+    // Draws the loaded window in its area with its status line, and the
+    // separator when another window sits to its right.
+    fn draw_window(&mut self, area: Rect, visible: &mut Visible) {
+        if self.view.w == 0 || self.view.h == 0 {
+            return;
+        }
+
+        if self.preview.is_some() {
+            self.render_preview();
+        } else {
+            self.render_row('~', visible);
+        }
+        self.draw_status_bar();
+
+        let right = self.view.x + self.view.w;
+        if right < area.x + area.w {
+            for row in self.view.y..self.view.y + self.view.h {
+                self.term_buf.push_str(&format!("\x1b[{};{}H\x1b[2m│\x1b[m", row + 1, right + 1));
+            }
+        }
+    }
+
+    fn render_row(&mut self, c: char, visible: &mut Visible) {
+        let mut filerow = self.rowoffset as usize;
+        let width = self.text_cols() as usize;
+        let height = self.text_rows() as usize;
+        let mut screen = Vec::new();
+
+        while screen.len() < height {
+            while filerow < visible.hidden.len() && visible.hidden[filerow] {
+                filerow += 1;
+            }
+
+            if filerow >= self.num_rows as usize {
+                let welcome = "Next Version 1.1";
 
+                if self.num_rows == 0 && screen.len() == height / 3 && width > welcome.len() + 1 {
+                    screen.push(format!("{}{}{}", c, " ".repeat((width - welcome.len()) / 2 - 1), welcome));
                 } else {
-                    self.term_buf.push(c);
+                    screen.push(c.to_string());
+                }
+            } else if self.wrap {
+                let skip = if filerow == self.rowoffset as usize { visible.skip } else { 0 };
+                let (text, segments, indent) = self.wrapped(visible, filerow);
+                for (n, &(start, end)) in segments.iter().enumerate().skip(skip) {
+                    let gutter = if n == 0 { self.gutter(filerow) } else { " ".repeat(self.gutter_width() as usize + indent) };
+                    screen.push(format!("{}{}", gutter, slice_columns(text, start, end - start)));
                 }
             } else {
-                let mut current_line = &self.rows[filerow as usize].row;
+                let text = self.display_line(filerow, &visible.hidden).0;
+                screen.push(format!("{}{}", self.gutter(filerow), slice_columns(&text, self.coloffset as usize, width)));
+            }
 
-                if self.cursor.cx != i || self.mode == Mode::Normal {
-                    current_line = &self.rows[filerow as usize].render;
-                }
+            filerow += 1;
+        }
 
-                if current_line.len() > self.size.0.into() {
-                    self.term_buf.push_str(&current_line[self.coloffset as usize..(self.coloffset + self.size.0 - 1).into()]);
-                } else {
-                    self.term_buf.push_str(current_line);
-                }
+        for (i, text) in screen.iter().take(height).enumerate() {
+            let padding = " ".repeat((self.view.w as usize).saturating_sub(visible_width(text)));
+            self.term_buf.push_str(&format!("\x1b[{};{}H{}{}", self.view.y + i as u16 + 1, self.view.x + 1, text, padding));
+        }
+    }
+
+    // The text drawn for `filerow` and the display column of each of its bytes.
+    fn display_line(&self, filerow: usize, hidden: &[bool]) -> (String, Vec<usize>) {
+        let line = &self.rows[filerow];
+        if self.shows_raw(filerow) {
+            render_text(line, Conceal::Raw)
+        } else if line.folded && hidden.get(filerow + 1) == Some(&true) {
+            let end = (filerow + 1..hidden.len()).find(|l| !hidden[*l]).unwrap_or(hidden.len());
+            (format!("{} \x1b[2m⋯ {} lines\x1b[0m", line.render, end - filerow - 1), line.cols.clone())
+        } else {
+            (line.render.clone(), line.cols.clone())
+        }
+    }
+
+    fn visible(&self) -> Visible {
+        Visible { hidden: self.hidden_lines(), wraps: HashMap::new(), skip: 0 }
+    }
+
+    // `wrap_rows` of `filerow`, worked out once per frame.
+    fn wrapped<'a>(&self, visible: &'a mut Visible, filerow: usize) -> &'a Wrapped {
+        let hidden = &visible.hidden;
+        visible.wraps.entry(filerow).or_insert_with(|| self.wrap_rows(filerow, hidden))
+    }
 
+    // The drawn text of `filerow` split into display rows when wrapping, with
+    // the hanging indent of the rows after the first.
+    fn wrap_rows(&self, filerow: usize, hidden: &[bool]) -> Wrapped {
+        let (text, cols) = self.display_line(filerow, hidden);
+        let width = self.text_cols() as usize;
+        if !self.wrap {
+            return (text, vec![(0, usize::MAX)], 0);
+        }
+
+        let row = &self.rows[filerow].row;
+        let indent = if self.breakindent {
+            let start = list_prefix(row).map_or(row.len() - row.trim_start().len(), |p| p.len);
+            cols.get(start).copied().unwrap_or(0)
+        } else {
+            0
+        };
+        let indent = hanging_indent(indent, width);
+        let segments = wrap_columns(&strip_escapes(&text), width, indent, self.linebreak);
+        (text, segments, indent)
+    }
+
+    // Where the cursor is drawn in the window, as row and column.
+    fn cursor_screen(&self, visible: &mut Visible) -> (u16, u16) {
+        let cx = self.cursor.cx as usize;
+        let col = self.cursor_col() as usize;
+        let shown: Vec<usize> = (self.rowoffset as usize..cx).filter(|l| !visible.hidden.get(*l).copied().unwrap_or(false)).collect();
+
+        if !self.wrap || cx >= self.num_rows as usize {
+            return (shown.len() as u16, self.gutter_width() + (col as u16).saturating_sub(self.coloffset));
+        }
+
+        let above: usize = shown.into_iter().map(|l| self.wrapped(visible, l).1.len()).sum();
+        let (_, segments, indent) = self.wrapped(visible, cx);
+        let n = segment_at(segments, col);
+        let col = col - segments[n].0 + if n > 0 { *indent } else { 0 };
+        ((above + n).saturating_sub(visible.skip) as u16, self.gutter_width() + col as u16)
+    }
+
+    // Moves the cursor a display row up or down, within a wrapped line or
+    // onto the next one, keeping its column on screen.
+    fn move_display_row(&mut self, down: bool) {
+        let cx = self.cursor.cx as usize;
+        if !self.wrap || cx >= self.num_rows as usize {
+            self.move_cursor(if down { Keys::Down } else { Keys::Up });
+            return;
+        }
+
+        let hidden = self.hidden_lines();
+        let (_, segments, indent) = self.wrap_rows(cx, &hidden);
+        let col = self.cursor_col() as usize;
+        let n = segment_at(&segments, col);
+        let offset = col - segments[n].0 + if n > 0 { indent } else { 0 };
+
+        let (target, segments, indent) = if down && n + 1 < segments.len() {
+            (n + 1, segments, indent)
+        } else if !down && n > 0 {
+            (n - 1, segments, indent)
+        } else {
+            self.move_cursor(if down { Keys::Down } else { Keys::Up });
+            if self.cursor.cx as usize == cx {
+                return;
             }
-                
-            self.term_buf.push_str("\x1b[K");
-            self.term_buf.push_str("\r\n");
+            let (_, segments, indent) = self.wrap_rows(self.cursor.cx as usize, &hidden);
+            (if down { 0 } else { segments.len() - 1 }, segments, indent)
+        };
+
+        let (start, end) = segments[target];
+        let col = start + offset.saturating_sub(if target > 0 { indent } else { 0 });
+        let col = if target + 1 < segments.len() { col.min(end.saturating_sub(1)) } else { col };
+        self.cursor_to_col(col);
+    }
+
+    // Moves the cursor to the start or the last character of its display row.
+    fn display_row_edge(&mut self, end: bool) {
+        let cx = self.cursor.cx as usize;
+        if cx >= self.num_rows as usize {
+            return;
+        }
+
+        let hidden = self.hidden_lines();
+        let (start, stop) = if self.wrap {
+            let (_, segments, _) = self.wrap_rows(cx, &hidden);
+            segments[segment_at(&segments, self.cursor_col() as usize)]
+        } else {
+            (self.coloffset as usize, self.coloffset as usize + self.text_cols() as usize)
+        };
+        self.cursor_to_col(if end { stop.saturating_sub(1) } else { start });
+    }
+
+    // Puts the cursor on the first byte of its line drawn at or after display
+    // column `col`.
+    fn cursor_to_col(&mut self, col: usize) {
+        let cx = self.cursor.cx as usize;
+        let len = self.rows[cx].row.len();
+        let cols = if self.shows_raw(cx) { render_text(&self.rows[cx], Conceal::Raw).1 } else { self.rows[cx].cols.clone() };
+        let last = cols.get(len.saturating_sub(1)).copied().unwrap_or(0);
+        self.cursor.cy = cols.iter().take(len).position(|c| *c >= col.min(last)).unwrap_or(0) as u16;
+    }
 
+    // The outline panel down the right of the windows.
+    fn draw_outline(&mut self, area: Rect) {
+        let width = self.outline_width();
+        let Some(outline) = self.outline.as_mut() else {
+            return;
+        };
+
+        let panel = outline.render(area.h, width - 1);
+        for i in 0..area.h {
+            let line = panel.get(i as usize).map_or("", |line| line.as_str());
+            self.term_buf.push_str(&format!("\x1b[{};{}H\x1b[2m│\x1b[m{}\x1b[K", area.y + i + 1, area.x + area.w + 1, line));
         }
     }
 
     fn move_cursor(&mut self, key: Keys) {
+        let prev = self.cursor.cx;
         match key {
             Keys::Left => if self.cursor.cy != 0 {
                 self.cursor.cy -= 1
@@ -247,7 +833,7 @@ impl Terminal {
                 self.cursor.cx -= 1;
                 self.cursor.cy = self.rows[self.cursor.cx as usize].row.len() as u16;
             },
-            Keys::Down => if self.cursor.cx < self.num_rows {
+            Keys::Down if self.cursor.cx < self.num_rows => {
                 self.cursor.cx += 1;
                 if self.cursor.cx < self.num_rows {
                     let row_len = self.rows[self.cursor.cx as usize].row.len() as u16;
@@ -258,7 +844,7 @@ impl Terminal {
                     self.cursor.cx = self.num_rows - 1;
                 }
             },
-            Keys::Up => if self.cursor.cx != 0 {
+            Keys::Up if self.cursor.cx != 0 => {
                 self.cursor.cx -= 1;
                 if self.cursor.cx < self.num_rows {
                     let row_len = self.rows[self.cursor.cx as usize].row.len() as u16;
@@ -267,19 +853,27 @@ impl Terminal {
                     }
                 }
             },
-            Keys::Right => if self.cursor.cx < self.num_rows {
+            Keys::Right if self.cursor.cx < self.num_rows => {
                 let row_len = self.rows[self.cursor.cx as usize].row.len() as u16;
                 if self.cursor.cy < row_len {
                     self.cursor.cy += 1;
-                } else if self.cursor.cy == row_len {
-                    if self.cursor.cx < self.num_rows - 1 {
-                        self.cursor.cx += 1;
-                        self.cursor.cy = 0;
-                    }
-                } 
-                },
+                } else if self.cursor.cy == row_len && self.cursor.cx < self.num_rows - 1 {
+                    self.cursor.cx += 1;
+                    self.cursor.cy = 0;
+                }
+            },
             _ => ()
         }
+
+        // Step over closed folds
+        if self.cursor.cx != prev {
+            let hidden = self.hidden_lines();
+            let mut cx = self.cursor.cx as usize;
+            while cx < hidden.len() && hidden[cx] {
+                if self.cursor.cx > prev { cx += 1 } else { cx -= 1 }
+            }
+            self.cursor.cx = if cx < self.num_rows as usize { cx as u16 } else { prev };
+        }
         
         if self.cursor.cy > self.rows[self.cursor.cx as usize].row.len() as u16 {
             self.cursor.cy = self.rows[self.cursor.cx as usize].row.len() as u16;
@@ -289,6 +883,15 @@ impl Terminal {
     fn handle_input(&mut self, key: Keys) {
         match self.mode {
             Mode::Normal => {
+                if let Keys::Char(c) = key {
+                    if !self.pending.is_empty() {
+                        self.pending.push(c);
+                        self.normal_sequence();
+                        return;
+                    }
+                }
+                self.pending.clear();
+
                 match key {
                     Keys::Char(c) => {
                         match c {
@@ -300,7 +903,7 @@ impl Terminal {
                                     self.mode = Mode::Insert;
                                     self.status = "-- INSERT --".to_string();
                                     if self.num_rows == 0 {
-                                        self.rows.insert(self.cursor.cx as usize, Line { row: "".to_string(), render: "".to_string() });
+                                        self.rows.insert(self.cursor.cx as usize, Line::new(String::new()));
                                         self.update_line(self.cursor.cx as usize);
                                         self.cursor.cy = 0;
                                         self.num_rows += 1;
@@ -320,14 +923,20 @@ impl Terminal {
                                     self.mode = Mode::Command;
                                     self.status = ":".to_string();
                                 },
+                            'z' | 'd' | 'y' | 'g' | 'm' | '\'' => self.pending.push(c),
+                            'p' => self.paste(true),
+                            'P' => self.paste(false),
+                            't' => self.toggle_task(None),
+                            'T' => self.toggle_task(Some(Date::today())),
                             _ => ()
 
                         }
                     },
-                Keys::Enter => {
-                    if self.cursor.cx < self.num_rows {
-                        self.move_cursor(Keys::Down);
-                    }
+                Keys::Enter if self.cursor.cx < self.num_rows && self.on_link() => {
+                    self.follow_link();
+                },
+                Keys::Enter if self.cursor.cx < self.num_rows => {
+                    self.move_cursor(Keys::Down);
                 },
                 Keys::BackSpace => {
                         self.move_cursor(Keys::Left);
@@ -343,25 +952,31 @@ impl Terminal {
                 Keys::Right => self.move_cursor(Keys::Right),
             
                 Keys::Home => self.cursor.cy = 0, 
-                Keys::End => if self.cursor.cx < self.num_rows { self.cursor.cy = self.rows[self.cursor.cx as usize].row.len() as u16},
+                Keys::End if self.cursor.cx < self.num_rows => self.cursor.cy = self.rows[self.cursor.cx as usize].row.len() as u16,
                 Keys::PageUp => {
                     self.cursor.cx = self.rowoffset;
-                    for _ in 0..self.size.1 {
+                    for _ in 0..self.text_rows() {
                         self.move_cursor(Keys::Up);
                     }
                 },
                 Keys::PageDown => {
-                    self.cursor.cx = self.rowoffset + self.size.1 - 1;
+                    self.cursor.cx = self.rowoffset + self.text_rows().saturating_sub(1);
                     if self.cursor.cx > self.num_rows {
                         self.cursor.cx = self.num_rows - 1;
                     }
-                    for _ in 0..self.size.1 {
+                    for _ in 0..self.text_rows() {
                         self.move_cursor(Keys::Down);
                     }
                 },
+                // Ctrl-W saves unless `winkeys` makes it the window prefix
+                Keys::SaveFile if self.winkeys => self.pending.push_str("^W"),
                 Keys::SaveFile => {
                         self.save();
                     }
+                Keys::JumpBack => self.jump_back(),
+                Keys::Find => self.open_finder(),
+                Keys::Tab => self.handle_tab(true),
+                Keys::BackTab => self.handle_tab(false),
                 _ => ()
                 }
             },
@@ -370,21 +985,46 @@ impl Terminal {
                     Keys::Char(c) => {
                         self.row_insert_char(self.cursor.cy as usize, c);
                         self.move_cursor(Keys::Right);
+                        if c != ' ' {
+                            self.auto_wrap();
+                        }
+                    },
+                    Keys::Enter if self.in_table() => {
+                        self.table_add_row();
                     },
+                    Keys::Tab => self.handle_tab(true),
+                    Keys::BackTab => self.handle_tab(false),
                     Keys::Enter => {
                         let line = self.rows[self.cursor.cx as usize].row.clone();
+                        let prefix = list_prefix(&line).filter(|p| self.cursor.cy as usize >= p.len);
+
+                        // Enter on an empty list item ends the list
+                        if prefix.as_ref().is_some_and(|p| line[p.len..].trim().is_empty()) {
+                            self.rows[self.cursor.cx as usize].row = String::new();
+                            self.update_line(self.cursor.cx as usize);
+                            self.cursor.cy = 0;
+                            self.dirty += 1;
+                            self.renumber_around(self.cursor.cx as usize);
+                            return;
+                        }
+
+                        let continuation = prefix.map(|p| p.continuation()).unwrap_or_default();
                         let currline = &line[..self.cursor.cy as usize];
-                        let newline = &line[self.cursor.cy as usize..];
+                        let newline = format!("{}{}", continuation, &line[self.cursor.cy as usize..]);
                         self.rows[self.cursor.cx as usize].row = currline.to_string();
                         self.update_line(self.cursor.cx as usize);
 
+                        // Marks go down with the text when the whole line moves
+                        let moved = if self.cursor.cy == 0 { self.cursor.cx } else { self.cursor.cx + 1 };
+                        self.shift_marks(moved as usize, 0, 1);
                         self.cursor.cx += 1;
-                        self.rows.insert(self.cursor.cx as usize, Line { row: newline.to_string(), render: newline.to_string() });
+                        self.rows.insert(self.cursor.cx as usize, Line::new(newline));
                         self.update_line(self.cursor.cx as usize);
 
-                        self.cursor.cy = 0;
+                        self.cursor.cy = continuation.len() as u16;
                         self.num_rows += 1;
                         self.dirty += 1;
+                        self.renumber_around(self.cursor.cx as usize);
                     },
                     Keys::BackSpace => {
                         let row_idx = self.cursor.cx as usize;
@@ -399,15 +1039,20 @@ impl Terminal {
                             let current_line = self.rows[row_idx].row.clone();
                             self.rows[row_idx - 1].row.push_str(&current_line);
                             self.rows.remove(row_idx);
+                            self.shift_marks(row_idx - 1, 2, 1);
 
                             self.cursor.cy = prev_line_len as u16;
                             self.update_line(row_idx - 1);
 
                             self.num_rows -= 1;
                             self.move_cursor(Keys::Up);
-                        }  
+                        }
+                        self.renumber_around(self.cursor.cx as usize);
                     },
                     Keys::Esc => {
+                        if self.in_table() {
+                            self.table_format();
+                        }
                         self.mode = Mode::Normal;
                         self.status = "-- NORMAL --".to_string();
                     },
@@ -417,25 +1062,54 @@ impl Terminal {
                     Keys::Right => self.move_cursor(Keys::Right),
                 
                     Keys::Home => self.cursor.cy = 0, 
-                    Keys::End => if self.cursor.cx < self.num_rows { self.cursor.cy = self.rows[self.cursor.cx as usize].row.len() as u16},
+                    Keys::End if self.cursor.cx < self.num_rows => self.cursor.cy = self.rows[self.cursor.cx as usize].row.len() as u16,
                     Keys::PageUp => {
                         self.cursor.cx = self.rowoffset;
-                        for _ in 0..self.size.1 {
+                        for _ in 0..self.text_rows() {
                             self.move_cursor(Keys::Up);
                         }
                     },
                     Keys::PageDown => {
-                        self.cursor.cx = self.rowoffset + self.size.1 - 1;
+                        self.cursor.cx = self.rowoffset + self.text_rows().saturating_sub(1);
                         if self.cursor.cx > self.num_rows {
                             self.cursor.cx = self.num_rows - 1;
                         }
-                        for _ in 0..self.size.1 {
+                        for _ in 0..self.text_rows() {
                             self.move_cursor(Keys::Down);
                         }
                     },
                     _ => ()
                 }
             },
+            Mode::List | Mode::Outline => {
+                match key {
+                    Keys::Char('j') | Keys::Down => {
+                        if let Some(list) = self.focused_list() {
+                            list.next();
+                        }
+                        self.list_preview();
+                    },
+                    Keys::Char('k') | Keys::Up => {
+                        if let Some(list) = self.focused_list() {
+                            list.prev();
+                        }
+                        self.list_preview();
+                    },
+                    Keys::Enter if self.rename.is_some() => self.apply_rename(),
+                    Keys::Enter if self.mode == Mode::List && self.list.as_ref().is_some_and(|list| list.kind == ListKind::Tags) => self.query_tag(),
+                    Keys::Enter => self.list_jump(),
+                    Keys::Char('q') => {
+                        if self.mode == Mode::Outline {
+                            self.outline = None;
+                        } else if self.list.is_none() {
+                            self.backlinks = None;
+                        }
+                        self.close_list();
+                    },
+                    Keys::Esc => self.close_list(),
+                    _ => ()
+                }
+            },
             Mode::Command => {
                 match key {
                     Keys::Char(c) => self.status.push(c),
@@ -451,45 +1125,108 @@ impl Terminal {
                     }
                     _ => ()
                 }
+            },
+            Mode::Find => {
+                let Some((root, finder)) = self.finder.as_mut() else {
+                    self.mode = Mode::Normal;
+                    return;
+                };
+                match key {
+                    Keys::Char(c) => {
+                        finder.query.push(c);
+                        finder.update();
+                    },
+                    Keys::BackSpace => {
+                        finder.query.pop();
+                        finder.update();
+                    },
+                    Keys::Down | Keys::Tab => finder.next(),
+                    Keys::Up | Keys::BackTab | Keys::Find => finder.prev(),
+                    Keys::Enter | Keys::SplitVertical | Keys::SplitHorizontal => {
+                        let path = finder.current().map(|entry| root.join(&entry.path));
+                        self.finder = None;
+                        self.mode = Mode::Normal;
+                        self.status = "-- NORMAL --".to_string();
+                        if let Some(path) = path {
+                            self.open_found(&path, key);
+                        }
+                    },
+                    Keys::Esc => {
+                        self.finder = None;
+                        self.mode = Mode::Normal;
+                        self.status = "-- NORMAL --".to_string();
+                    },
+                    _ => ()
+                }
+            },
+            Mode::Prompt => {
+                let field = self.new_note.as_ref().and_then(|note| note.fields.get(note.values.len())).cloned().unwrap_or_default();
+                let prompt = format!("{}: ", field);
+                match key {
+                    Keys::Char(c) => self.status.push(c),
+                    Keys::BackSpace if self.status.len() > prompt.len() => {
+                        self.status.pop();
+                    },
+                    Keys::Esc => {
+                        self.new_note = None;
+                        self.mode = Mode::Normal;
+                        self.status = "Cancelled".to_string();
+                    },
+                    Keys::Enter => {
+                        let value = self.status.get(prompt.len()..).unwrap_or("").to_string();
+                        if let Some(note) = self.new_note.as_mut() {
+                            note.values.push((field, value));
+                        }
+                        self.next_prompt();
+                    },
+                    _ => ()
+                }
             }
         }
     }
 
     fn draw_status_bar(&mut self) {
-        self.term_buf.push_str("\x1b[7m");
+        let style = if self.window_id == self.focus { "\x1b[7m" } else { "\x1b[2;7m" };
 
         let mut filename = "[No Name]".to_string();
         if let Some(name) = &self.filename {
-            filename = name.to_string(); 
+            filename = name.to_string();
+        }
+        if self.preview.is_some() {
+            filename.push_str(" [Preview]");
         }
-        
-        let status = format!(" {}{}- {} lines", filename, if self.dirty != 0 {" (modified) "} else {""} , self.rows.len());
 
-        let len = status.len();
-        let cursor = format!("{},{} ", self.cursor.cy, self.cursor.cx);
-        let len2 = cursor.len();
+        let width = self.view.w as usize;
+        let status = truncate(&format!(" {}{}- {} lines", filename, if self.dirty != 0 {" (modified) "} else {""} , self.rows.len()), self.view.w);
+
+        let len = status.chars().count();
+        let mut cursor = format!("{},{} ", self.cursor.cy, self.cursor.cx);
+        if len + cursor.len() > width {
+            cursor.clear();
+        }
+        self.term_buf.push_str(&format!("\x1b[{};{}H{}", self.view.y + self.view.h, self.view.x + 1, style));
         self.term_buf.push_str(&status);
-        self.term_buf.push_str(&" ".repeat(self.size.0 as usize - len - len2));
+        self.term_buf.push_str(&" ".repeat(width.saturating_sub(len + cursor.len())));
         self.term_buf.push_str(&cursor);
 
         self.term_buf.push_str("\x1b[m");
-        self.term_buf.push_str("\r\n");
     }
 
     fn draw_message_bar(&mut self) {
+        self.term_buf.push_str(&format!("\x1b[{};1H", self.size.1 + 2));
         self.term_buf.push_str("\x1b[K");
 
-        self.term_buf.push_str(&self.status);
+        self.term_buf.push_str(&truncate(&self.status, self.size.0));
 
     }
 
     fn row_insert_char(&mut self, at: usize, c: char) {
-        let at = if at > self.rows[self.cursor.cx as usize].row.len() { self.rows[self.cursor.cx as usize].row.len() as usize } else { at };
+        let at = if at > self.rows[self.cursor.cx as usize].row.len() { self.rows[self.cursor.cx as usize].row.len() } else { at };
         if self.cursor.cx < self.num_rows {
             self.rows[self.cursor.cx as usize].row.insert(at, c);
             self.update_line(self.cursor.cx as usize);
         } else {
-            self.rows.push(Line { row: String::new(), render: String::new() });
+            self.rows.push(Line::new(String::new()));
             self.rows[self.cursor.cx as usize].row.insert(at, c);
             self.update_line(self.cursor.cx as usize);
         }
@@ -498,7 +1235,14 @@ impl Terminal {
     }
 
     fn execute_command(&mut self) {
-        let status: Vec<&str> = self.status.split_whitespace().collect();
+        let command = self.status.clone();
+        let status: Vec<&str> = command.split_whitespace().collect();
+        self.mode = Mode::Normal;
+
+        if status.is_empty() {
+            self.status = "-- NORMAL --".to_string();
+            return;
+        }
 
         match status[0] {
             ":w" => {
@@ -506,21 +1250,2103 @@ impl Terminal {
                     self.filename = Some(status[1].to_string());
                     self.save();
                     self.status = "-- NORMAL --".to_string();
-                    self.mode = Mode::Normal;
+                } else if self.filename.is_some() {
+                    self.save();
                 } else {
+                    self.mode = Mode::Command;
                     self.status = ":w ".to_string();
                 }
             },
+            ":q" if self.editing_windows().len() > 1 || !self.tabs.is_empty() => {
+                self.close_window();
+            },
             ":q" => {
                 println!("Exiting.");
                 clear_screen();
                 exit(0);
             }
-            _ => {
-                self.status = "Invalid Command".to_string();
-                self.mode = Mode::Normal;
-            }
-        }
-    }
-
+            ":split" | ":sp" => {
+                self.split_window(false, status.get(1).map(Path::new));
+            },
+            ":vsplit" | ":vs" => {
+                self.split_window(true, status.get(1).map(Path::new));
+            },
+            ":close" | ":clo" => {
+                self.close_window();
+            },
+            ":only" | ":on" => {
+                self.only_window();
+            },
+            ":wincmd" | ":winc" => {
+                self.pending = format!("^W{}", status.get(1).copied().unwrap_or(""));
+                self.normal_sequence();
+                self.pending.clear();
+            },
+            ":Preview" => {
+                self.toggle_preview();
+            },
+            ":tabnew" => {
+                self.new_tab(status.get(1).map(Path::new));
+            },
+            ":tabclose" | ":tabc" => {
+                self.close_tab();
+            },
+            ":TableAddColumn" => {
+                self.table_command(|table, cell| {
+                    table.add_column(cell + 1);
+                    cell + 1
+                });
+            },
+            ":TableDeleteColumn" => {
+                self.table_command(|table, cell| {
+                    table.delete_column(cell);
+                    cell.min(table.columns() - 1)
+                });
+            },
+            ":TableSort" => {
+                let col = status.get(1).and_then(|c| c.parse::<usize>().ok());
+                self.table_command(|table, cell| {
+                    let col = col.map(|c| c.saturating_sub(1)).unwrap_or(cell);
+                    table.sort(col);
+                    col.min(table.columns() - 1)
+                });
+            },
+            ":TableFromCSV" => {
+                self.table_from_csv();
+            },
+            ":Tasks" => {
+                self.show_tasks();
+            },
+            ":Outline" => {
+                self.focus_outline();
+            },
+            ":export" => {
+                self.export(status.get(1).copied(), status.get(2).copied());
+            },
+            ":Promote" => {
+                self.section_shift_level(-1);
+            },
+            ":Demote" => {
+                self.section_shift_level(1);
+            },
+            ":SectionUp" => {
+                self.section_move(false);
+            },
+            ":SectionDown" => {
+                self.section_move(true);
+            },
+            ":NoteRename" => {
+                self.note_rename(&status[1..].join(" "));
+            },
+            ":NoteUndo" => {
+                self.undo_rename();
+            },
+            ":grep" => {
+                self.grep(command[5..].trim());
+            },
+            ":cfile" | ":cf" => {
+                self.quickfix_file(status.get(1).copied());
+            },
+            ":copen" => {
+                self.quickfix_open();
+            },
+            ":cclose" => {
+                if self.list.as_ref().is_some_and(|list| list.kind == ListKind::Quickfix) {
+                    self.list = None;
+                }
+                self.status = "-- NORMAL --".to_string();
+            },
+            ":cn" | ":cnext" => {
+                self.quickfix_go(self.quickfix_idx + 1);
+            },
+            ":cp" | ":cprev" => {
+                match self.quickfix_idx.checked_sub(1) {
+                    Some(idx) => self.quickfix_go(idx),
+                    None if self.quickfix.is_empty() => self.quickfix_go(0),
+                    None => self.status = "No previous entry".to_string(),
+                }
+            },
+            ":nohlsearch" | ":noh" => {
+                self.search = None;
+                self.status = "-- NORMAL --".to_string();
+            },
+            ":cdo" => {
+                self.quickfix_do(command[4..].trim());
+            },
+            cmd if is_substitute(cmd) => {
+                self.substitute(&command[2..]);
+            },
+            ":Find" => {
+                self.open_finder();
+            },
+            ":New" => {
+                self.new_note(status.get(1).copied().unwrap_or(""), &status.get(2..).unwrap_or(&[]).join(" "));
+            },
+            ":Today" => {
+                self.open_journal(0);
+            },
+            ":Yesterday" => {
+                self.open_journal(-1);
+            },
+            ":Tomorrow" => {
+                self.open_journal(1);
+            },
+            ":Journal" => {
+                self.show_journal(status.get(1).copied());
+            },
+            ":Tags" => {
+                self.show_tags();
+            },
+            ":Query" => {
+                self.query(&status[1..]);
+            },
+            ":Backlinks" => {
+                self.toggle_backlinks();
+            },
+            ":NoteSearch" => {
+                self.note_search(&status[1..].join(" "));
+            },
+            ":set" => {
+                self.status = "-- NORMAL --".to_string();
+                for option in status[1..].iter() {
+                    self.set_option(option);
+                }
+            },
+            _ => {
+                self.status = "Invalid Command".to_string();
+                self.mode = Mode::Normal;
+            }
+        }
+    }
+
+    fn replace_lines(&mut self, start: usize, end: usize, lines: Vec<String>) {
+        let count = lines.len();
+        self.shift_marks(start, end - start, count);
+        self.rows.splice(start..end, lines.into_iter().map(Line::new));
+        for idx in start..start + count {
+            self.update_line(idx);
+        }
+
+        self.num_rows = self.rows.len() as u16;
+        self.dirty += 1;
+    }
+
+    fn in_table(&self) -> bool {
+        table_bounds(&self.rows, self.cursor.cx as usize).is_some()
+    }
+
+    // Parses the table under the cursor along with the row and cell the cursor is in.
+    fn current_table(&self) -> Option<(usize, usize, Table, usize, usize)> {
+        let (start, end) = table_bounds(&self.rows, self.cursor.cx as usize)?;
+        let lines: Vec<String> = self.rows[start..end].iter().map(|line| line.row.clone()).collect();
+        let table = Table::parse(&lines);
+
+        let line = &lines[self.cursor.cx as usize - start];
+        let row = table.row_of_line(self.cursor.cx as usize - start);
+        let cell = cell_at(line, self.cursor.cy as usize).min(table.columns() - 1);
+
+        Some((start, end, table, row, cell))
+    }
+
+    fn write_table(&mut self, start: usize, end: usize, table: &Table, row: usize, cell: usize) {
+        let lines = table.format();
+        let line = Table::line_of_row(row);
+        let col = cell_col(&lines[line], cell);
+
+        self.replace_lines(start, end, lines);
+        self.cursor.cx = (start + line) as u16;
+        self.cursor.cy = col as u16;
+    }
+
+    fn table_move(&mut self, forward: bool) {
+        let Some((start, end, mut table, mut row, mut cell)) = self.current_table() else {
+            return;
+        };
+
+        if forward {
+            if cell + 1 < table.columns() {
+                cell += 1;
+            } else {
+                row += 1;
+                cell = 0;
+                if row == table.rows.len() {
+                    table.insert_row(row);
+                }
+            }
+        } else if cell > 0 {
+            cell -= 1;
+        } else if row > 0 {
+            row -= 1;
+            cell = table.columns() - 1;
+        }
+
+        self.write_table(start, end, &table, row, cell);
+    }
+
+    fn table_add_row(&mut self) {
+        let Some((start, end, mut table, row, _)) = self.current_table() else {
+            return;
+        };
+
+        table.insert_row(row + 1);
+        self.write_table(start, end, &table, (row + 1).max(1), 0);
+    }
+
+    // Lines up the pipes while keeping the cursor at the same spot in its cell.
+    fn table_format(&mut self) {
+        let Some((start, end, table, row, cell)) = self.current_table() else {
+            return;
+        };
+
+        let line = &self.rows[self.cursor.cx as usize].row;
+        let offset = (self.cursor.cy as usize).saturating_sub(cell_col(line, cell));
+
+        self.write_table(start, end, &table, row, cell);
+
+        let content = table.rows[row][cell].len();
+        self.cursor.cy += offset.min(content) as u16;
+    }
+
+    fn table_command<F>(&mut self, f: F) where F: FnOnce(&mut Table, usize) -> usize {
+        self.mode = Mode::Normal;
+        let Some((start, end, mut table, row, cell)) = self.current_table() else {
+            self.status = "Not inside a table".to_string();
+            return;
+        };
+
+        let cell = f(&mut table, cell);
+        self.write_table(start, end, &table, row, cell);
+        self.status = "-- NORMAL --".to_string();
+    }
+
+    // Turns the comma separated paragraph under the cursor into a table.
+    fn table_from_csv(&mut self) {
+        self.mode = Mode::Normal;
+        let cx = self.cursor.cx as usize;
+        if cx >= self.rows.len() || self.rows[cx].row.trim().is_empty() {
+            self.status = "No CSV under cursor".to_string();
+            return;
+        }
+
+        let mut start = cx;
+        while start > 0 && !self.rows[start - 1].row.trim().is_empty() {
+            start -= 1;
+        }
+        let mut end = cx + 1;
+        while end < self.rows.len() && !self.rows[end].row.trim().is_empty() {
+            end += 1;
+        }
+
+        let lines: Vec<String> = self.rows[start..end].iter().map(|line| line.row.clone()).collect();
+        let table = Table::from_csv(&lines);
+        self.write_table(start, end, &table, 0, 0);
+        self.status = format!("Converted {} lines into a table", lines.len());
+    }
+
+    fn toggle_task(&mut self, stamp: Option<Date>) {
+        let cx = self.cursor.cx as usize;
+        if cx >= self.rows.len() {
+            return;
+        }
+
+        if let Some(row) = toggle_task(&self.rows[cx].row, stamp) {
+            self.rows[cx].row = row;
+            self.update_line(cx);
+            self.dirty += 1;
+
+            let len = self.rows[cx].row.len() as u16;
+            if self.cursor.cy > len {
+                self.cursor.cy = len;
+            }
+        }
+    }
+
+    fn show_tasks(&mut self) {
+        let items: Vec<ListItem> = self.rows.iter().enumerate()
+            .filter(|(_, line)| task_state(&line.row) == Some(false))
+            .map(|(i, line)| ListItem { text: format!("{}: {}", i + 1, line.row.trim()), line: i, col: 0, file: None })
+            .collect();
+
+        self.open_list(ListKind::Tasks, "Tasks".to_string(), items);
+    }
+
+    fn open_list(&mut self, kind: ListKind, title: String, items: Vec<ListItem>) {
+        self.list = Some(ListView::new(kind, title, items));
+        self.mode = Mode::List;
+        self.status = "j/k to move, Enter to jump, Esc to close".to_string();
+        self.list_preview();
+    }
+
+    // Closes the list panel, or just leaves the outline while keeping it open.
+    fn close_list(&mut self) {
+        if self.mode == Mode::List {
+            self.list = None;
+        }
+        self.rename = None;
+        self.mode = Mode::Normal;
+        self.status = "-- NORMAL --".to_string();
+    }
+
+    fn focused_list(&mut self) -> Option<&mut ListView> {
+        match self.mode {
+            Mode::Outline => self.outline.as_mut(),
+            _ => self.list.as_mut().or(self.backlinks.as_mut()),
+        }
+    }
+
+    // Moves the cursor to the selected entry so the text follows the list.
+    fn list_preview(&mut self) {
+        let Some((line, col, file)) = self.focused_list().and_then(|list| list.current()).map(|item| (item.line, item.col, item.file.clone())) else {
+            return;
+        };
+        if file.is_some_and(|file| !self.is_current(&file)) {
+            return;
+        }
+
+        if line < self.rows.len() {
+            self.cursor.cx = line as u16;
+            self.cursor.cy = col.min(self.rows[line].row.len()) as u16;
+        }
+    }
+
+    // Jumps to the selected entry, opening its note when it lives in another file.
+    fn list_jump(&mut self) {
+        if let Some(list) = self.list.as_ref().filter(|list| self.mode == Mode::List && list.kind == ListKind::Quickfix) {
+            self.quickfix_idx = list.selected;
+        }
+        let file = self.focused_list().and_then(|list| list.current()).and_then(|item| item.file.clone());
+        match file {
+            Some(file) if !self.is_current(&file) => {
+                let (line, col) = self.focused_list().and_then(|list| list.current()).map(|item| (item.line, item.col)).unwrap_or((0, 0));
+                let exists = Path::new(&file).exists();
+                self.close_list();
+                if let Err(err) = self.open_note(Path::new(&file)) {
+                    self.status = err.to_string();
+                    return;
+                }
+                // New notes keep the cursor their template put them at
+                if !exists || !self.is_current(&file) {
+                    return;
+                }
+                self.cursor.cx = line.min(self.num_rows.saturating_sub(1) as usize) as u16;
+                self.cursor.cy = col.min(self.rows[self.cursor.cx as usize].row.len()) as u16;
+            },
+            _ => {
+                self.list_preview();
+                self.close_list();
+            },
+        }
+    }
+
+    // Opens the outline panel, or focuses it when it is already open.
+    fn focus_outline(&mut self) {
+        if self.outline.is_none() {
+            self.outline = Some(ListView::new(ListKind::Outline, "Outline".to_string(), Vec::new()));
+        }
+        self.update_outline();
+        self.mode = Mode::Outline;
+        self.status = "j/k to move, Enter to jump, Esc to leave, q to close".to_string();
+    }
+
+    // Rebuilds the outline from the buffer so it follows headings as they are typed.
+    fn update_outline(&mut self) {
+        let Some(outline) = self.outline.as_mut() else {
+            return;
+        };
+
+        let headings = headings(&self.rows);
+        let top = headings.iter().map(|h| h.level).min().unwrap_or(1);
+        outline.items = headings.iter().map(|h| ListItem {
+            text: format!("{}{}", "  ".repeat(h.level - top), h.text),
+            line: h.line,
+            col: 0,
+            file: None,
+        }).collect();
+
+        if self.mode != Mode::Outline {
+            outline.selected = headings.iter().rposition(|h| h.line <= self.cursor.cx as usize).unwrap_or(0);
+        }
+        outline.selected = outline.selected.min(outline.items.len().saturating_sub(1));
+    }
+
+    fn draw_list(&mut self) {
+        let Some(list) = self.list.as_mut().or(self.backlinks.as_mut()) else {
+            return;
+        };
+
+        let height = list.height(self.size.1 / 2);
+        let top = self.size.1 - height + 2;
+        for (i, line) in list.render(height, self.size.0).iter().take(height as usize).enumerate() {
+            self.term_buf.push_str(&format!("\x1b[{};1H", top + i as u16));
+            self.term_buf.push_str(line);
+            self.term_buf.push_str("\x1b[K");
+        }
+    }
+
+    fn handle_tab(&mut self, forward: bool) {
+        if self.in_table() {
+            self.table_move(forward);
+        } else {
+            self.indent_list_item(forward);
+        }
+    }
+
+    // Changes the nesting level of the list item under the cursor.
+    fn indent_list_item(&mut self, forward: bool) {
+        let cx = self.cursor.cx as usize;
+        if cx >= self.rows.len() || list_prefix(&self.rows[cx].row).is_none() {
+            return;
+        }
+
+        let row = &self.rows[cx].row;
+        if forward {
+            self.rows[cx].row = format!("    {}", row);
+            self.cursor.cy += 4;
+        } else {
+            let removed = if row.starts_with('\t') { 1 } else { row.len() - row.trim_start_matches(' ').len() }.min(4);
+            if removed == 0 {
+                return;
+            }
+            self.rows[cx].row = row[removed..].to_string();
+            self.cursor.cy = self.cursor.cy.saturating_sub(removed as u16);
+        }
+
+        self.update_line(cx);
+        self.dirty += 1;
+        self.renumber_around(cx);
+    }
+
+    // Renumbers the ordered lists touching line `idx`.
+    fn renumber_around(&mut self, idx: usize) {
+        for i in idx.saturating_sub(1)..(idx + 2).min(self.rows.len()) {
+            for (line, row) in renumber(&self.rows, i) {
+                if line == self.cursor.cx as usize {
+                    let delta = row.len() as i32 - self.rows[line].row.len() as i32;
+                    self.cursor.cy = (self.cursor.cy as i32 + delta).max(0) as u16;
+                }
+                self.rows[line].row = row;
+                self.update_line(line);
+            }
+        }
+    }
+
+    fn set_option(&mut self, option: &str) {
+        let (name, value) = option.split_once('=').unwrap_or((option, ""));
+        match name {
+            "conceallevel" | "cole" => match Conceal::from_level(value) {
+                Some(conceal) => {
+                    self.conceal = conceal;
+                    let current = self.window_id;
+                    let windows: Vec<usize> = self.windows.iter().map(|w| w.id).collect();
+                    for id in windows {
+                        self.load_window(id);
+                        for idx in 0..self.rows.len() {
+                            self.update_line(idx);
+                        }
+                    }
+                    self.load_window(current);
+                },
+                None => self.status = format!("Invalid value: {}", option),
+            },
+            "wrap" => self.wrap = true,
+            "nowrap" => self.wrap = false,
+            "linebreak" | "lbr" => self.linebreak = true,
+            "nolinebreak" | "nolbr" => self.linebreak = false,
+            "breakindent" | "bri" => self.breakindent = true,
+            "nobreakindent" | "nobri" => self.breakindent = false,
+            "textwidth" | "tw" => match value.parse() {
+                Ok(width) => self.textwidth = width,
+                Err(_) => self.status = format!("Invalid value: {}", option),
+            },
+            "number" | "nu" => self.number = true,
+            "nonumber" | "nonu" => self.number = false,
+            "relativenumber" | "rnu" => self.relativenumber = true,
+            "norelativenumber" | "nornu" => self.relativenumber = false,
+            "signcolumn" | "scl" => match value {
+                "yes" => self.signcolumn = Some(true),
+                "no" => self.signcolumn = Some(false),
+                "auto" => self.signcolumn = None,
+                _ => self.status = format!("Invalid value: {}", option),
+            },
+            "winkeys" => self.winkeys = true,
+            "nowinkeys" => self.winkeys = false,
+            "notesroot" => self.notes_root = if value.is_empty() { None } else { Some(PathBuf::from(value)) },
+            _ => self.status = format!("Unknown option: {}", name),
+        }
+    }
+
+    fn normal_sequence(&mut self) {
+        match self.pending.as_str() {
+            "za" => self.fold(None),
+            "zc" => self.fold(Some(true)),
+            "zo" => self.fold(Some(false)),
+            "zM" => self.fold_all(true),
+            "zR" => self.fold_all(false),
+            "das" => self.section_delete(false),
+            "dis" => self.section_delete(true),
+            "yas" => self.section_yank(false),
+            "yis" => self.section_yank(true),
+            "gf" => self.follow_link(),
+            "gj" => self.move_display_row(true),
+            "gk" => self.move_display_row(false),
+            "g0" => self.display_row_edge(false),
+            "g$" => self.display_row_edge(true),
+            "gt" => self.go_tab(self.tab + 1),
+            "gT" => self.go_tab(self.tab + self.tabs.len()),
+            seq if seq.starts_with("gq") => match self.line_motion(&seq[2..], "gq") {
+                Motion::Pending => return,
+                Motion::Lines(start, end) => self.format_lines(start, end),
+                Motion::Invalid => (),
+            },
+            "^Wh" => self.focus_neighbour('h'),
+            "^Wj" => self.focus_neighbour('j'),
+            "^Wk" => self.focus_neighbour('k'),
+            "^Wl" => self.focus_neighbour('l'),
+            "^Ww" => self.focus_next(),
+            "^W=" => self.layout.equalize(),
+            "^Ws" => self.split_window(false, None),
+            "^Wv" => self.split_window(true, None),
+            "^Wc" | "^Wq" => self.close_window(),
+            "^Wo" => self.only_window(),
+            seq if seq.len() == 2 && seq.starts_with('m') => self.set_mark(seq.chars().nth(1).unwrap_or(' ')),
+            seq if seq.len() == 2 && seq.starts_with('\'') => self.jump_to_mark(seq.chars().nth(1).unwrap_or(' ')),
+            "d" | "da" | "di" | "y" | "ya" | "yi" | "^W" => return,
+            _ => (),
+        }
+        self.pending.clear();
+    }
+
+    // Lines hidden inside closed folds.
+    fn hidden_lines(&self) -> Vec<bool> {
+        let mut hidden = vec![false; self.rows.len()];
+        let headings = headings(&self.rows);
+
+        for (i, heading) in headings.iter().enumerate() {
+            if self.rows[heading.line].folded && !hidden[heading.line] {
+                let end = section_end(&headings, i, self.rows.len());
+                hidden[heading.line + 1..end].fill(true);
+            }
+        }
+
+        hidden
+    }
+
+    // Closes (Some(true)), opens (Some(false)) or toggles the fold around the cursor.
+    fn fold(&mut self, close: Option<bool>) {
+        let headings = headings(&self.rows);
+        let Some(idx) = section_at(&headings, self.cursor.cx as usize) else {
+            self.status = "No fold found".to_string();
+            return;
+        };
+
+        let line = headings[idx].line;
+        self.rows[line].folded = close.unwrap_or(!self.rows[line].folded);
+        if self.rows[line].folded {
+            self.cursor.cx = line as u16;
+            self.cursor.cy = 0;
+        }
+    }
+
+    fn fold_all(&mut self, close: bool) {
+        for heading in headings(&self.rows) {
+            self.rows[heading.line].folded = close;
+        }
+    }
+
+    // Line range of the section around the cursor, heading excluded when `inner`.
+    fn section_range(&self, inner: bool) -> Option<(usize, usize)> {
+        let headings = headings(&self.rows);
+        let idx = section_at(&headings, self.cursor.cx as usize)?;
+        let start = headings[idx].line;
+        let end = section_end(&headings, idx, self.rows.len());
+
+        if !inner {
+            return Some((start, end));
+        }
+        let body = if start + 1 < end && is_setext_underline(&self.rows, start + 1) { start + 2 } else { start + 1 };
+        Some((body.min(end), end))
+    }
+
+    // Wraps the line being typed once it runs past textwidth.
+    fn auto_wrap(&mut self) {
+        let idx = self.cursor.cx as usize;
+        if self.textwidth == 0 || self.cursor_col() as usize <= self.textwidth {
+            return;
+        }
+
+        let line = &self.rows[idx];
+        let (lines, (row, col)) = reflow(std::slice::from_ref(&line.row), &[line.code || line.meta], self.textwidth, (0, self.cursor.cy as usize));
+        if lines.len() < 2 {
+            return;
+        }
+
+        self.replace_lines(idx, idx + 1, lines);
+        self.cursor.cx = (idx + row) as u16;
+        self.cursor.cy = col as u16;
+    }
+
+    // The lines covered by `motion` typed after operator `op`: an optional
+    // count then `j`, `k`, `G`, `gg`, `{`, `}`, `ip` or `ap`, or the
+    // operator's last key again for the current lines.
+    fn line_motion(&self, motion: &str, op: &str) -> Motion {
+        let digits = motion.find(|c: char| !c.is_ascii_digit()).unwrap_or(motion.len());
+        let count = motion[..digits].parse::<usize>().ok().filter(|n| *n > 0);
+        let n = count.unwrap_or(1);
+        let last = self.rows.len().saturating_sub(1);
+        let cx = (self.cursor.cx as usize).min(last);
+        let blank = |idx: usize| self.rows[idx].row.trim().is_empty();
+
+        let target = match &motion[digits..] {
+            "" | "g" | "i" | "a" => return Motion::Pending,
+            m if m.len() < op.len() && op.starts_with(m) => return Motion::Pending,
+            m if m == op || m == &op[op.len() - 1..] => (cx + n - 1).min(last),
+            "j" => (cx + n).min(last),
+            "k" => cx.saturating_sub(n),
+            "G" => count.map_or(last, |n| (n - 1).min(last)),
+            "gg" => count.map_or(0, |n| (n - 1).min(last)),
+            // The next blank line past some text, or the end
+            m @ ("}" | "{") => {
+                let mut l = cx;
+                for _ in 0..n {
+                    let mut text = !blank(l);
+                    while (m == "}" && l < last) || (m == "{" && l > 0) {
+                        l = if m == "}" { l + 1 } else { l - 1 };
+                        if !blank(l) {
+                            text = true;
+                        } else if text {
+                            break;
+                        }
+                    }
+                }
+                l
+            },
+            "ip" | "ap" => {
+                let (start, end) = self.paragraph_range();
+                return Motion::Lines(start, end);
+            },
+            _ => return Motion::Invalid,
+        };
+        Motion::Lines(cx.min(target), cx.max(target) + 1)
+    }
+
+    // The run of non-blank lines around the cursor.
+    fn paragraph_range(&self) -> (usize, usize) {
+        let blank = |idx: usize| self.rows[idx].row.trim().is_empty();
+        let cx = self.cursor.cx as usize;
+        if self.rows.is_empty() || blank(cx) {
+            return (cx, cx);
+        }
+
+        let start = (0..cx).rev().find(|idx| blank(*idx)).map_or(0, |idx| idx + 1);
+        let end = (cx + 1..self.rows.len()).find(|idx| blank(*idx)).unwrap_or(self.rows.len());
+        (start, end)
+    }
+
+    // Reflows lines `start..end` to textwidth, or 79 columns when it is off.
+    fn format_lines(&mut self, start: usize, end: usize) {
+        let end = end.min(self.rows.len());
+        if start >= end {
+            return;
+        }
+
+        let width = if self.textwidth == 0 { 79 } else { self.textwidth };
+        let old: Vec<String> = self.rows[start..end].iter().map(|line| line.row.clone()).collect();
+        let keep: Vec<bool> = self.rows[start..end].iter().map(|line| line.code || line.meta).collect();
+        let (lines, _) = reflow(&old, &keep, width, (0, 0));
+
+        let count = lines.len();
+        if lines != old {
+            self.replace_lines(start, end, lines);
+        }
+        self.cursor.cx = (start + count - 1) as u16;
+        self.cursor.cy = 0;
+    }
+
+    fn section_yank(&mut self, inner: bool) {
+        let Some((start, end)) = self.section_range(inner) else {
+            self.status = "No section found".to_string();
+            return;
+        };
+
+        self.register = self.rows[start..end].iter().map(|line| line.row.clone()).collect();
+        self.status = format!("{} lines yanked", end - start);
+    }
+
+    fn section_delete(&mut self, inner: bool) {
+        let Some((start, end)) = self.section_range(inner) else {
+            self.status = "No section found".to_string();
+            return;
+        };
+
+        self.register = self.rows[start..end].iter().map(|line| line.row.clone()).collect();
+        self.replace_lines(start, end, Vec::new());
+        if self.rows.is_empty() {
+            self.rows = vec![Line::new(String::new())];
+            self.num_rows = 0;
+        }
+
+        self.cursor.cx = start.min(self.rows.len() - 1) as u16;
+        self.cursor.cy = 0;
+        self.status = format!("{} lines deleted", end - start);
+    }
+
+    fn paste(&mut self, after: bool) {
+        if self.register.is_empty() {
+            return;
+        }
+
+        let at = if self.num_rows == 0 { 0 } else if after { self.cursor.cx as usize + 1 } else { self.cursor.cx as usize };
+        let end = if self.num_rows == 0 { self.rows.len() } else { at };
+        self.replace_lines(at, end, self.register.clone());
+        self.cursor.cx = at as u16;
+        self.cursor.cy = 0;
+    }
+
+    // Promotes (-1) or demotes (1) the heading under the cursor along with its subheadings.
+    fn section_shift_level(&mut self, delta: i32) {
+        let headings = headings(&self.rows);
+        let Some(idx) = section_at(&headings, self.cursor.cx as usize) else {
+            self.status = "No section found".to_string();
+            return;
+        };
+
+        let Some(edits) = shift_section(&self.rows, &headings, idx, delta) else {
+            self.status = "Heading level out of range".to_string();
+            return;
+        };
+
+        for (start, end, heading) in edits {
+            let folded = self.rows[start].folded;
+            self.replace_lines(start, end, vec![heading]);
+            self.rows[start].folded = folded;
+        }
+
+        self.cursor.cx = headings[idx].line as u16;
+        self.cursor.cy = 0;
+        self.status = "-- NORMAL --".to_string();
+    }
+
+    // Swaps the section under the cursor with its previous or next sibling.
+    fn section_move(&mut self, down: bool) {
+        let headings = headings(&self.rows);
+        let Some(idx) = section_at(&headings, self.cursor.cx as usize) else {
+            self.status = "No section found".to_string();
+            return;
+        };
+
+        let Some((first, second, last)) = sibling_swap(&headings, idx, down, self.rows.len()) else {
+            self.status = "No sibling section".to_string();
+            return;
+        };
+
+        let folds: Vec<bool> = self.rows[first..last].iter().map(|line| line.folded).collect();
+        let mut lines: Vec<String> = self.rows[second..last].iter().map(|line| line.row.clone()).collect();
+        lines.extend(self.rows[first..second].iter().map(|line| line.row.clone()));
+        self.replace_lines(first, last, lines);
+
+        for (i, folded) in folds[second - first..].iter().chain(folds[..second - first].iter()).enumerate() {
+            self.rows[first + i].folded = *folded;
+        }
+
+        self.cursor.cx = if down { first + last - second } else { first } as u16;
+        self.cursor.cy = 0;
+        self.status = "-- NORMAL --".to_string();
+    }
+
+    fn export(&mut self, format: Option<&str>, output: Option<&str>) {
+        if format != Some("html") {
+            self.status = "Usage: :export html [file]".to_string();
+            return;
+        }
+
+        let output = match (output, &self.filename) {
+            (Some(output), _) => output.to_string(),
+            (None, Some(filename)) => default_output(filename, "html"),
+            (None, None) => {
+                self.status = "No file name, use :export html <file>".to_string();
+                return;
+            }
+        };
+
+        self.status = match export_html(&self.rows, self.filename.as_deref(), &output) {
+            Ok(()) => format!("Exported to {}", output),
+            Err(err) => err.to_string(),
+        };
+    }
+
+    // Wiki links resolve from here: the workspace, else the current note's directory.
+    fn notes_root(&self) -> PathBuf {
+        if let Some(root) = self.workspace_root() {
+            return root;
+        }
+
+        self.note_dir()
+    }
+
+    fn note_dir(&self) -> PathBuf {
+        self.filename.as_ref()
+            .and_then(|f| Path::new(f).parent())
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .map(|dir| dir.to_path_buf())
+            .unwrap_or_else(|| PathBuf::from("."))
+    }
+
+    // The workspace set with `:set notesroot=`, or found from a `.next/` folder.
+    fn workspace_root(&self) -> Option<PathBuf> {
+        match &self.notes_root {
+            Some(root) => Some(root.clone()),
+            None => discover(&self.note_dir()),
+        }
+    }
+
+    fn on_link(&self) -> bool {
+        link_at(&self.rows[self.cursor.cx as usize].row, self.cursor.cy as usize).is_some()
+    }
+
+    fn follow_link(&mut self) {
+        if self.cursor.cx as usize >= self.rows.len() {
+            return;
+        }
+
+        let root = self.notes_root();
+        let (path, heading) = match link_at(&self.rows[self.cursor.cx as usize].row, self.cursor.cy as usize) {
+            Some(Link::Wiki { target, heading }) => (resolve_wiki(&root, &target), heading),
+            Some(Link::File { path, heading }) if path.is_empty() => {
+                // `[text](#heading)` stays in this note
+                self.jumps.push((self.buffer_id, self.cursor));
+                self.jump_to_heading(heading.as_deref().unwrap_or(""));
+                return;
+            },
+            Some(Link::File { path, heading }) => (resolve_file(&root, self.filename.as_deref(), &path), heading),
+            Some(Link::Url(url)) => {
+                self.status = format!("External link: {}", url);
+                return;
+            },
+            None => {
+                self.status = "No link under cursor".to_string();
+                return;
+            },
+        };
+
+        if let Err(err) = self.open_note(&path) {
+            self.status = err.to_string();
+            return;
+        }
+        if let Some(heading) = heading {
+            self.jump_to_heading(&heading);
+        }
+    }
+
+    // Shows `path`, reusing its buffer when it is already open and starting
+    // missing notes from the template.
+    fn open_note(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let filename = display_path(path);
+        let path = Path::new(&filename);
+        if self.is_current(&filename) {
+            self.jumps.push((self.buffer_id, self.cursor));
+            return Ok(());
+        }
+
+        let buffer = match self.buffers.iter().position(|b| b.filename.as_ref().is_some_and(|f| same_file(f, &filename))) {
+            Some(idx) => self.buffers.remove(idx),
+            None if path.exists() => {
+                let rows = read_lines(&filename)?;
+                self.new_buffer(filename.clone(), rows, 0, Cursor { cx: 0, cy: 0 })
+            },
+            None => {
+                let name = if journal_date(path).is_some() { "journal" } else { "default" };
+                return self.create_note(path, name);
+            },
+        };
+
+        if !self.is_scratch() {
+            self.jumps.push((self.buffer_id, self.cursor));
+        }
+        self.show_buffer(buffer);
+        self.status = filename;
+        Ok(())
+    }
+
+    fn new_buffer(&mut self, filename: String, rows: Vec<Line>, dirty: u32, cursor: Cursor) -> Buffer {
+        self.load_git_changes(&filename);
+        self.next_buffer_id += 1;
+        Buffer { id: self.next_buffer_id - 1, num_rows: rows.len() as u16, rows, filename: Some(filename), dirty, cursor, rowoffset: 0, coloffset: 0 }
+    }
+
+    // Starts a note at `path` from the named template, asking for the
+    // template's custom fields in the message bar first.
+    fn create_note(&mut self, path: &Path, name: &str) -> Result<(), Box<dyn Error>> {
+        let Some(template) = template(&self.notes_root(), name) else {
+            return Err(format!("No template {}", name).into());
+        };
+
+        let context = TemplateContext {
+            title: note_title(path),
+            filename: path.file_name().map(|f| f.to_string_lossy().to_string()).unwrap_or_default(),
+            date: journal_date(path).unwrap_or_else(Date::today),
+            time: seconds_today(),
+        };
+        let fields = template_fields(&template);
+        self.new_note = Some(NewNote { path: path.to_path_buf(), template, context, fields, values: Vec::new() });
+        self.next_prompt();
+        Ok(())
+    }
+
+    // Asks for the next template field, or creates the note once all are known.
+    fn next_prompt(&mut self) {
+        let Some(note) = self.new_note.as_ref() else {
+            return;
+        };
+        if let Some(field) = note.fields.get(note.values.len()) {
+            self.mode = Mode::Prompt;
+            self.status = format!("{}: ", field);
+            return;
+        }
+
+        let Some(note) = self.new_note.take() else {
+            return;
+        };
+        self.mode = Mode::Normal;
+
+        let (text, cursor) = expand_template(&note.template, &note.context, &note.values);
+        let mut rows: Vec<Line> = text.split('\n').map(|line| Line::new(line.to_string())).collect();
+        if rows.len() > 1 && rows.last().is_some_and(|line| line.row.is_empty()) && cursor.is_none_or(|(line, _)| line < rows.len() - 1) {
+            rows.pop();
+        }
+        let (cx, cy) = cursor.unwrap_or((0, 0));
+        let filename = display_path(&note.path);
+        let buffer = self.new_buffer(filename.clone(), rows, 1, Cursor { cx: cx as u16, cy: cy as u16 });
+
+        if !self.is_scratch() {
+            self.jumps.push((self.buffer_id, self.cursor));
+        }
+        self.show_buffer(buffer);
+        self.status = format!("New note {}", filename);
+    }
+
+    // Creates a note named `title` in the notes root from `.next/templates/<name>.md`.
+    fn new_note(&mut self, name: &str, title: &str) {
+        if name.is_empty() || title.trim().is_empty() {
+            self.status = "Usage: :New <template> <title>".to_string();
+            return;
+        }
+
+        let path = self.notes_root().join(format!("{}.md", title.trim()));
+        let result = if path.exists() { self.open_note(&path) } else { self.create_note(&path, name) };
+        if let Err(err) = result {
+            self.status = err.to_string();
+        }
+    }
+
+    // An untouched empty buffer is replaced rather than kept in the background.
+    fn is_scratch(&self) -> bool {
+        self.filename.is_none() && self.dirty == 0 && self.num_rows == 0 && !self.windows.iter().any(|w| w.buffer == self.buffer_id)
+    }
+
+    fn is_current(&self, filename: &str) -> bool {
+        self.filename.as_ref().is_some_and(|f| same_file(f, filename))
+    }
+
+    // Puts `buffer` on screen and keeps the current document in the background.
+    fn show_buffer(&mut self, buffer: Buffer) {
+        let scratch = self.is_scratch();
+        let previous = self.swap_buffer(buffer);
+        if !scratch {
+            self.buffers.push(previous);
+        }
+
+        self.list = None;
+        if self.rows.is_empty() {
+            self.rows.push(Line::new(String::new()));
+        }
+        for idx in 0..self.rows.len() {
+            self.update_line(idx);
+        }
+        self.update_backlinks();
+    }
+
+    // Loads `buffer` as it is, handing back the one it replaces.
+    fn swap_buffer(&mut self, buffer: Buffer) -> Buffer {
+        let previous = Buffer {
+            id: self.buffer_id,
+            rows: std::mem::replace(&mut self.rows, buffer.rows),
+            num_rows: self.num_rows,
+            filename: std::mem::replace(&mut self.filename, buffer.filename),
+            dirty: self.dirty,
+            cursor: self.cursor,
+            rowoffset: self.rowoffset,
+            coloffset: self.coloffset,
+        };
+
+        self.buffer_id = buffer.id;
+        self.num_rows = buffer.num_rows;
+        self.dirty = buffer.dirty;
+        self.cursor = buffer.cursor;
+        self.rowoffset = buffer.rowoffset;
+        self.coloffset = buffer.coloffset;
+        previous
+    }
+
+    fn jump_to_heading(&mut self, name: &str) {
+        let target = slug(name);
+        match headings(&self.rows).iter().find(|h| slug(&h.text) == target) {
+            Some(heading) => {
+                self.cursor.cx = heading.line as u16;
+                self.cursor.cy = 0;
+            },
+            None => self.status = format!("No heading {}", name),
+        }
+    }
+
+    fn jump_back(&mut self) {
+        let Some((id, cursor)) = self.jumps.pop() else {
+            self.status = "Jump list is empty".to_string();
+            return;
+        };
+
+        if id != self.buffer_id {
+            let Some(idx) = self.buffers.iter().position(|b| b.id == id) else {
+                return;
+            };
+            let buffer = self.buffers.remove(idx);
+            self.show_buffer(buffer);
+            self.status = self.filename.clone().unwrap_or_else(|| "[No Name]".to_string());
+        }
+
+        self.cursor.cx = cursor.cx.min(self.num_rows.saturating_sub(1));
+        self.cursor.cy = cursor.cy;
+    }
+
+    // The search index of the current workspace, loaded on first use.
+    fn workspace_index(&mut self) -> Option<&mut Index> {
+        let root = self.workspace_root()?;
+        if self.index.as_ref().map(|index| &index.root) != Some(&root) {
+            self.index = Some(Index::open(&root));
+        }
+        self.index.as_mut()
+    }
+
+    // Keeps the index in step with a note that was just saved.
+    fn index_file(&mut self, path: &Path) {
+        if path.extension().is_none_or(|ext| ext != "md") {
+            return;
+        }
+        let Some(index) = self.workspace_index() else {
+            return;
+        };
+        if index.update_file(path) {
+            let _ = index.save();
+        }
+    }
+
+    fn note_search(&mut self, query: &str) {
+        if query.trim().is_empty() {
+            self.status = "Usage: :NoteSearch <query>".to_string();
+            return;
+        }
+        let Some(index) = self.workspace_index() else {
+            self.status = "Not in a workspace, create a .next/ folder or :set notesroot=<dir>".to_string();
+            return;
+        };
+
+        let root = index.root.clone();
+        let hits = index.search(query);
+        let items: Vec<ListItem> = hits.iter().take(100).filter_map(|hit| {
+            let text = fs::read_to_string(&hit.path).ok()?;
+            let (line, col, context) = snippet(&text, query).unwrap_or((0, 0, String::new()));
+            let name = hit.path.strip_prefix(&root).unwrap_or(&hit.path).to_string_lossy().to_string();
+            Some(ListItem {
+                text: format!("{}:{}: {}", name, line + 1, context),
+                line,
+                col,
+                file: Some(display_path(&hit.path)),
+            })
+        }).collect();
+
+        self.open_list(ListKind::Search, format!("Search: {}", query), items);
+    }
+
+    // Opens the backlinks panel below the text, or closes it when it is open.
+    fn toggle_backlinks(&mut self) {
+        if self.backlinks.take().is_some() {
+            if self.mode == Mode::List && self.list.is_none() {
+                self.mode = Mode::Normal;
+            }
+            self.status = "-- NORMAL --".to_string();
+            return;
+        }
+        if self.filename.is_none() {
+            self.status = "No file name, save the note first".to_string();
+            return;
+        }
+
+        self.backlinks = Some(ListView::new(ListKind::Backlinks, "Backlinks".to_string(), Vec::new()));
+        self.update_backlinks();
+        self.mode = Mode::List;
+        self.status = "j/k to move, Enter to open, Esc to leave, q to close".to_string();
+    }
+
+    fn update_backlinks(&mut self) {
+        if self.backlinks.is_none() {
+            return;
+        }
+
+        let items = self.backlink_items();
+        if let Some(backlinks) = self.backlinks.as_mut() {
+            backlinks.title = format!("Backlinks: {}", self.filename.as_deref().map(|f| note_title(Path::new(f))).unwrap_or_default());
+            backlinks.items = items;
+            backlinks.selected = backlinks.selected.min(backlinks.items.len().saturating_sub(1));
+        }
+    }
+
+    // Every line of another note that links to the current one.
+    fn backlink_items(&mut self) -> Vec<ListItem> {
+        let Some(filename) = self.filename.clone() else {
+            return Vec::new();
+        };
+        let target = PathBuf::from(&filename);
+
+        // The index narrows the search down to notes mentioning the name
+        let (root, candidates) = match self.workspace_index() {
+            Some(index) => (index.root.clone(), index.containing(&note_title(&target))),
+            None => {
+                let root = self.notes_root();
+                let files = note_files(&root);
+                (root, files)
+            },
+        };
+
+        let mut items = Vec::new();
+        for path in candidates {
+            if same_file(&path.to_string_lossy(), &filename) {
+                continue;
+            }
+            let Ok(text) = fs::read_to_string(&path) else {
+                continue;
+            };
+            let name = path.strip_prefix(&root).unwrap_or(&path).to_string_lossy().to_string();
+            for (line, col, context) in backlinks(&root, &path, &text, &target) {
+                items.push(ListItem {
+                    text: format!("{}:{}: {}", name, line + 1, context),
+                    line,
+                    col,
+                    file: Some(display_path(&path)),
+                });
+            }
+        }
+
+        items
+    }
+
+    fn has_unsaved(&self) -> bool {
+        self.dirty != 0 || self.buffers.iter().any(|b| b.dirty != 0)
+    }
+
+    // Previews renaming the current note and the links that will follow it.
+    fn note_rename(&mut self, name: &str) {
+        let name = name.trim();
+        let name = name.strip_suffix(".md").unwrap_or(name);
+        if name.is_empty() || name.contains('/') {
+            self.status = "Usage: :NoteRename <new-name>".to_string();
+            return;
+        }
+        let Some(filename) = self.filename.clone() else {
+            self.status = "No file name, save the note first".to_string();
+            return;
+        };
+        if self.has_unsaved() {
+            self.status = "Save your changes before renaming".to_string();
+            return;
+        }
+
+        let from = PathBuf::from(&filename);
+        let to = from.with_file_name(format!("{}.md", name));
+        if to.exists() {
+            self.status = format!("{} already exists", to.display());
+            return;
+        }
+
+        let files = self.link_rewrites(&from, name);
+        let mut items = Vec::new();
+        let mut links = 0;
+        for (path, before, after, count) in files.iter() {
+            let display = display_path(path);
+            let changed = before.lines().zip(after.lines()).enumerate().filter(|(_, (old, new))| old != new);
+            for (line, (_, new)) in changed {
+                items.push(ListItem { text: format!("{}:{}: {}", display, line + 1, new.trim()), line, col: 0, file: Some(display.clone()) });
+            }
+            links += count;
+        }
+
+        let title = format!("Rename {} to {}: {} links in {} notes", note_title(&from), name, links, files.len());
+        self.open_list(ListKind::Rename, title, items);
+        let files = files.into_iter().map(|(path, before, after, _)| (path, before, after)).collect();
+        self.rename = Some(Rename { from, to, files });
+        self.status = "Enter to rename, Esc to cancel".to_string();
+    }
+
+    // The notes linking to `note` with their text before and after pointing
+    // those links at `name`, and how many links change in each.
+    fn link_rewrites(&mut self, note: &Path, name: &str) -> Vec<(PathBuf, String, String, usize)> {
+        let (root, mut candidates) = match self.workspace_index() {
+            Some(index) => (index.root.clone(), index.containing(&note_title(note))),
+            None => {
+                let root = self.notes_root();
+                let files = note_files(&root);
+                (root, files)
+            },
+        };
+        if !candidates.iter().any(|path| same_file(&path.to_string_lossy(), &note.to_string_lossy())) {
+            candidates.push(note.to_path_buf());
+        }
+
+        let mut files = Vec::new();
+        for path in candidates {
+            let Ok(before) = fs::read_to_string(&path) else {
+                continue;
+            };
+            let (after, count) = rewrite_links(&root, &path, &before, note, name);
+            if count > 0 {
+                files.push((path, before, after, count));
+            }
+        }
+        files
+    }
+
+    fn apply_rename(&mut self) {
+        let Some(mut rename) = self.rename.take() else {
+            return;
+        };
+        self.close_list();
+
+        let own = rename.files.iter().position(|(path, _, _)| same_file(&path.to_string_lossy(), &rename.from.to_string_lossy()));
+        match self.move_note(&rename.from, &rename.to, &rename.files) {
+            Ok(()) => {
+                // The note's own links are looked for where it now lives
+                if let Some(own) = own {
+                    rename.files[own].0 = rename.to.clone();
+                }
+                self.status = format!("Renamed to {}, {} notes updated (:NoteUndo to revert)", rename.to.display(), rename.files.len());
+                self.last_rename = Some(rename);
+            },
+            Err(err) => self.status = err.to_string(),
+        }
+    }
+
+    // Moves the note back and points the links at it back at the old name.
+    // Notes untouched since the rename get their old text back; in notes
+    // edited since, only the links are rewritten so the edits are kept.
+    fn undo_rename(&mut self) {
+        if self.last_rename.is_none() {
+            self.status = "Nothing to undo".to_string();
+            return;
+        }
+        if self.has_unsaved() {
+            self.status = "Save your changes before undoing the rename".to_string();
+            return;
+        }
+
+        let Some(rename) = self.last_rename.take() else {
+            return;
+        };
+        if rename.from.exists() {
+            self.status = format!("{} exists again, not undoing the rename", rename.from.display());
+            self.last_rename = Some(rename);
+            return;
+        }
+
+        // Notes may have gained links to the new name since
+        if let Some(index) = self.workspace_index() {
+            if index.refresh() {
+                let _ = index.save();
+            }
+        }
+
+        let mut edited = 0;
+        let files: Vec<(PathBuf, String, String)> = self.link_rewrites(&rename.to, &note_title(&rename.from)).into_iter().map(|(path, now, relinked, _)| {
+            let renamed = rename.files.iter().find(|(old, _, _)| same_file(&old.to_string_lossy(), &path.to_string_lossy()));
+            match renamed {
+                Some((_, before, after)) if *after == now => (path, now, before.clone()),
+                _ => {
+                    edited += 1;
+                    (path, now, relinked)
+                },
+            }
+        }).collect();
+
+        match self.move_note(&rename.to, &rename.from, &files) {
+            Ok(()) if edited > 0 => self.status = format!("Renamed back to {}, relinked {} notes edited since", rename.from.display(), edited),
+            Ok(()) => self.status = format!("Renamed back to {}", rename.from.display()),
+            Err(err) => {
+                self.status = err.to_string();
+                self.last_rename = Some(rename);
+            },
+        }
+    }
+
+    // Writes the linking notes and moves the note `from` to `to` as one
+    // batch, then reloads the buffers showing any of them.
+    fn move_note(&mut self, from: &Path, to: &Path, files: &[(PathBuf, String, String)]) -> Result<(), Box<dyn Error>> {
+        let moved = |name: &str| if same_file(name, &from.to_string_lossy()) {
+            Some(Path::new(name).with_file_name(to.file_name().unwrap_or_default()).to_string_lossy().to_string())
+        } else {
+            files.iter().any(|(path, _, _)| same_file(name, &path.to_string_lossy())).then(|| name.to_string())
+        };
+        let current = self.filename.as_deref().and_then(moved);
+        let stashed: Vec<(usize, String)> = self.buffers.iter().enumerate()
+            .filter_map(|(i, b)| b.filename.as_deref().and_then(moved).map(|name| (i, name)))
+            .collect();
+
+        write_batch(files, from, to)?;
+
+        for (i, name) in stashed {
+            let rows = read_lines(&name)?;
+            let buffer = &mut self.buffers[i];
+            buffer.num_rows = rows.len() as u16;
+            buffer.cursor.cx = buffer.cursor.cx.min(buffer.num_rows.saturating_sub(1));
+            buffer.cursor.cy = 0;
+            buffer.rows = rows;
+            buffer.filename = Some(name);
+        }
+        if let Some(name) = current {
+            self.rows = read_lines(&name)?;
+            if self.rows.is_empty() {
+                self.rows.push(Line::new(String::new()));
+            }
+            self.num_rows = self.rows.len() as u16;
+            self.cursor.cx = self.cursor.cx.min(self.num_rows.saturating_sub(1));
+            self.cursor.cy = self.cursor.cy.min(self.rows[self.cursor.cx as usize].row.len() as u16);
+            self.load_git_changes(&name);
+            self.filename = Some(name);
+            for idx in 0..self.rows.len() {
+                self.update_line(idx);
+            }
+        }
+
+        if let Some(index) = self.workspace_index() {
+            if index.refresh() {
+                let _ = index.save();
+            }
+        }
+        self.update_backlinks();
+
+        Ok(())
+    }
+
+    // Every tag used in the notes, most used first.
+    fn show_tags(&mut self) {
+        let mut counts: HashMap<String, (String, usize)> = HashMap::new();
+        for path in note_files(&self.notes_root()) {
+            let Ok(text) = fs::read_to_string(&path) else {
+                continue;
+            };
+            for tag in tags(&text) {
+                counts.entry(tag.to_lowercase()).or_insert((tag, 0)).1 += 1;
+            }
+        }
+
+        let mut counts: Vec<(String, usize)> = counts.into_values().collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.to_lowercase().cmp(&b.0.to_lowercase())));
+        let items = counts.into_iter().map(|(tag, count)| ListItem { text: format!("#{} ({})", tag, count), line: 0, col: 0, file: None }).collect();
+
+        self.open_list(ListKind::Tags, "Tags".to_string(), items);
+        self.status = "j/k to move, Enter to list the notes, Esc to close".to_string();
+    }
+
+    fn query_tag(&mut self) {
+        let Some(tag) = self.list.as_ref().and_then(|list| list.current()).and_then(|item| item.text.rsplit_once(" (")).map(|(tag, _)| tag.to_string()) else {
+            return;
+        };
+        self.close_list();
+        self.query(&[&tag]);
+    }
+
+    // Lists the notes matching every term, e.g. `tag:work status:open`.
+    fn query(&mut self, terms: &[&str]) {
+        if terms.is_empty() {
+            self.status = "Usage: :Query tag:<tag> <field>:<value> <word>".to_string();
+            return;
+        }
+
+        let (root, candidates) = match self.workspace_index() {
+            Some(index) => (index.root.clone(), index.containing(&query_words(terms))),
+            None => {
+                let root = self.notes_root();
+                let files = note_files(&root);
+                (root, files)
+            },
+        };
+
+        let mut items = Vec::new();
+        for path in candidates {
+            let Ok(text) = fs::read_to_string(&path) else {
+                continue;
+            };
+            if !matches_query(&text, terms) {
+                continue;
+            }
+
+            let title = title(&text).unwrap_or_else(|| note_title(&path));
+            let name = path.strip_prefix(&root).unwrap_or(&path).to_string_lossy().to_string();
+            items.push(ListItem {
+                text: format!("{}: {}", name, title),
+                line: 0,
+                col: 0,
+                file: Some(display_path(&path)),
+            });
+        }
+
+        self.open_list(ListKind::Query, format!("Query: {}", terms.join(" ")), items);
+    }
+
+    // Opens the daily note `offset` days from today, creating it from the journal template.
+    pub fn open_journal(&mut self, offset: i64) {
+        let path = journal_path(&self.notes_root(), Date::today().add_days(offset));
+        if let Err(err) = self.open_note(&path) {
+            self.status = err.to_string();
+        }
+    }
+
+    // Lists the days of a month, marking the ones with a journal entry.
+    fn show_journal(&mut self, month: Option<&str>) {
+        let today = Date::today();
+        let first = match month {
+            Some(month) => match Date::parse(&format!("{}-01", month)) {
+                Some(date) => date,
+                None => {
+                    self.status = "Usage: :Journal [YYYY-MM]".to_string();
+                    return;
+                },
+            },
+            None => Date { day: 1, ..today },
+        };
+
+        let root = self.notes_root();
+        let names = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+        let mut entries = 0;
+        let items: Vec<ListItem> = (1..=days_in_month(first.year, first.month)).map(|day| {
+            let date = Date { day, ..first };
+            let path = journal_path(&root, date);
+            let exists = path.exists();
+            entries += exists as usize;
+            ListItem {
+                text: format!("{} {}{}{}", names[date.weekday()], date, if exists { "  ●" } else { "" }, if date == today { "  (today)" } else { "" }),
+                line: 0,
+                col: 0,
+                file: Some(display_path(&path)),
+            }
+        }).collect();
+
+        self.open_list(ListKind::Journal, format!("Journal {}-{:02}: {} entries", first.year, first.month, entries), items);
+        if let Some(list) = self.list.as_mut() {
+            if today.year == first.year && today.month == first.month {
+                list.selected = today.day as usize - 1;
+            }
+        }
+        self.status = "j/k to move, Enter to open the day, Esc to close".to_string();
+    }
+
+    // Opens the fuzzy finder over the files of the workspace.
+    fn open_finder(&mut self) {
+        let root = self.notes_root();
+        let entries = project_files(&root).into_iter().map(|path| {
+            let title = if path.extension().is_some_and(|ext| ext == "md") {
+                read_title(&path)
+            } else {
+                None
+            };
+            Entry { path: path.strip_prefix(&root).unwrap_or(&path).to_string_lossy().to_string(), title }
+        }).collect();
+
+        self.finder = Some((root, Finder::new(entries)));
+        self.mode = Mode::Find;
+        self.status = "Type to filter, Enter to open, Ctrl-V/Ctrl-S to split, Esc to close".to_string();
+    }
+
+    fn open_found(&mut self, path: &Path, key: Keys) {
+        match key {
+            Keys::SplitVertical => self.split_window(true, Some(path)),
+            Keys::SplitHorizontal => self.split_window(false, Some(path)),
+            _ => if let Err(err) = self.open_note(path) {
+                self.status = err.to_string();
+            },
+        }
+    }
+
+    // The finder popup, centred over the text area.
+    fn draw_finder(&mut self) {
+        let area = self.layout_area();
+        let width = (area.w * 3 / 4).clamp(20.min(area.w), 100);
+        let height = area.h.min(16);
+        let left = (area.w - width) / 2 + 1;
+        let top = area.y + (area.h - height) / 2 + 1;
+        let Some((_, finder)) = self.finder.as_mut() else {
+            return;
+        };
+
+        let lines = finder.render(height, width);
+        if height < 4 || width < 4 {
+            return;
+        }
+        let query = finder.query.chars().count() as u16;
+        for (i, line) in lines.iter().enumerate() {
+            self.term_buf.push_str(&format!("\x1b[{};{}H{}", top + i as u16, left, line));
+        }
+        self.term_buf.push_str(&format!("\x1b[{};{}H", top + 1, left + 3 + query.min(width.saturating_sub(4))));
+    }
+
+    // Searches the files of the workspace for a regex, optionally only those
+    // matching a glob, and fills the quickfix list.
+    fn grep(&mut self, args: &str) {
+        let (pattern, glob) = match args.chars().next() {
+            Some(quote @ ('"' | '\'')) => match args[1..].split_once(quote) {
+                Some((pattern, rest)) => (pattern.to_string(), rest.trim()),
+                None => (args[1..].to_string(), ""),
+            },
+            _ => match args.split_once(char::is_whitespace) {
+                Some((pattern, rest)) => (pattern.to_string(), rest.trim()),
+                None => (args.to_string(), ""),
+            },
+        };
+        if pattern.is_empty() {
+            self.status = "Usage: :grep <pattern> [glob]".to_string();
+            return;
+        }
+
+        let re = match Regex::new(&pattern) {
+            Ok(re) => re,
+            Err(err) => {
+                self.status = err.to_string().lines().last().unwrap_or("Invalid pattern").to_string();
+                return;
+            },
+        };
+        let files = match files_matching(&self.notes_root(), Some(glob).filter(|g| !g.is_empty())) {
+            Ok(files) => files,
+            Err(err) => {
+                self.status = err.to_string();
+                return;
+            },
+        };
+
+        self.search = Some(re.clone());
+        let mut items = Vec::new();
+        for path in files {
+            // Binary and unreadable files are skipped
+            let Ok(text) = fs::read_to_string(&path) else {
+                continue;
+            };
+            let name = display_path(&path);
+            for (line, row) in text.lines().enumerate() {
+                if let Some(m) = re.find(row) {
+                    items.push(ListItem { text: format!("{}:{}:{}: {}", name, line + 1, m.start() + 1, row.trim()), line, col: m.start(), file: Some(name.clone()) });
+                }
+            }
+        }
+
+        self.set_quickfix(items);
+    }
+
+    // Loads compiler style `file:line:col: message` lines into the quickfix list.
+    fn quickfix_file(&mut self, file: Option<&str>) {
+        let Some(file) = file else {
+            self.status = "Usage: :cfile <file>".to_string();
+            return;
+        };
+        let text = match fs::read_to_string(file) {
+            Ok(text) => text,
+            Err(err) => {
+                self.status = format!("{}: {}", file, err);
+                return;
+            },
+        };
+
+        let re = Regex::new(r#"^(.+?):(\d+):(?:(\d+):)?\s*(.*)$"#).unwrap();
+        let items = text.lines().filter_map(|line| {
+            let caps = re.captures(line)?;
+            let row: usize = caps[2].parse().ok()?;
+            let col: usize = caps.get(3).and_then(|c| c.as_str().parse().ok()).unwrap_or(1);
+            Some(ListItem { text: line.to_string(), line: row.saturating_sub(1), col: col.saturating_sub(1), file: Some(caps[1].to_string()) })
+        }).collect::<Vec<ListItem>>();
+
+        self.diagnostics = items.clone();
+        self.set_quickfix(items);
+    }
+
+    fn set_quickfix(&mut self, items: Vec<ListItem>) {
+        self.quickfix = items;
+        self.quickfix_idx = 0;
+        if self.quickfix.is_empty() {
+            self.status = "No matches".to_string();
+            return;
+        }
+        self.quickfix_go(0);
+    }
+
+    fn quickfix_open(&mut self) {
+        self.open_list(ListKind::Quickfix, "Quickfix".to_string(), self.quickfix.clone());
+        if let Some(list) = self.list.as_mut() {
+            list.selected = self.quickfix_idx.min(list.items.len().saturating_sub(1));
+        }
+        self.list_preview();
+    }
+
+    // Jumps to quickfix entry `idx`.
+    fn quickfix_go(&mut self, idx: usize) {
+        if self.quickfix.is_empty() {
+            self.status = "Quickfix list is empty".to_string();
+            return;
+        }
+        if idx >= self.quickfix.len() {
+            self.status = "No more entries".to_string();
+            return;
+        }
+
+        self.quickfix_idx = idx;
+        let item = self.quickfix[idx].clone();
+        if let Some(file) = &item.file {
+            if let Err(err) = self.open_note(Path::new(file)) {
+                self.status = err.to_string();
+                return;
+            }
+        }
+        if item.file.as_ref().is_none_or(|file| self.is_current(file)) {
+            self.cursor.cx = item.line.min(self.num_rows.saturating_sub(1) as usize) as u16;
+            self.cursor.cy = item.col.min(self.rows[self.cursor.cx as usize].row.len()) as u16;
+        }
+        if let Some(list) = self.list.as_mut().filter(|list| list.kind == ListKind::Quickfix) {
+            list.selected = idx;
+        }
+        self.status = format!("({} of {}) {}", idx + 1, self.quickfix.len(), item.text);
+    }
+
+    // Runs ex commands, separated by `|`, at every quickfix entry.
+    fn quickfix_do(&mut self, commands: &str) {
+        if commands.is_empty() {
+            self.status = "Usage: :cdo <command> [| <command>]".to_string();
+            return;
+        }
+
+        for idx in 0..self.quickfix.len() {
+            self.quickfix_go(idx);
+            for command in commands.split('|').map(str::trim).filter(|c| !c.is_empty()) {
+                self.status = format!(":{}", command.trim_start_matches(':'));
+                self.execute_command();
+            }
+        }
+        self.status = format!("Ran {} on {} entries", commands, self.quickfix.len());
+    }
+
+    // `:s/pattern/replacement/[g]` on the cursor line.
+    fn substitute(&mut self, args: &str) {
+        let Some(delimiter) = args.chars().next() else {
+            return;
+        };
+        let parts = split_unescaped(&args[delimiter.len_utf8()..], delimiter);
+        let (pattern, replacement) = (&parts[0], parts.get(1).map_or("", |r| r.as_str()));
+        let global = parts.get(2).is_some_and(|flags| flags.contains('g'));
+
+        let re = match Regex::new(pattern) {
+            Ok(re) => re,
+            Err(err) => {
+                self.status = err.to_string().lines().last().unwrap_or("Invalid pattern").to_string();
+                return;
+            },
+        };
+        let cx = self.cursor.cx as usize;
+        if cx >= self.rows.len() {
+            return;
+        }
+
+        let row = &self.rows[cx].row;
+        let new = if global { re.replace_all(row, replacement) } else { re.replace(row, replacement) }.to_string();
+        if new == *row {
+            self.status = format!("Pattern not found: {}", pattern);
+            return;
+        }
+        self.replace_lines(cx, cx + 1, vec![new]);
+        self.cursor.cy = self.cursor.cy.min(self.rows[cx].row.len() as u16);
+        self.status = "-- NORMAL --".to_string();
+    }
+
+    fn current_window(&self) -> Window {
+        Window { id: self.window_id, buffer: self.buffer_id, cursor: self.cursor, rowoffset: self.rowoffset, coloffset: self.coloffset, preview: self.preview }
+    }
+
+    // Loads window `id` with its buffer and place, keeping the current one in
+    // the background. Nothing is re-rendered so it is cheap enough per frame.
+    fn load_window(&mut self, id: usize) {
+        if id == self.window_id {
+            return;
+        }
+        let Some(idx) = self.windows.iter().position(|w| w.id == id) else {
+            return;
+        };
+
+        let window = self.windows.remove(idx);
+        self.windows.push(self.current_window());
+        if window.buffer != self.buffer_id {
+            if let Some(idx) = self.buffers.iter().position(|b| b.id == window.buffer) {
+                let buffer = self.buffers.remove(idx);
+                let previous = self.swap_buffer(buffer);
+                self.buffers.push(previous);
+            }
+        }
+
+        self.window_id = window.id;
+        self.cursor = window.cursor;
+        self.rowoffset = window.rowoffset;
+        self.coloffset = window.coloffset;
+        self.preview = window.preview;
+    }
+
+    // Moves into window `id`, unless it is a preview, which only ever follows.
+    fn focus_window(&mut self, id: usize) {
+        if self.windows.iter().any(|w| w.id == id && w.preview.is_some()) {
+            return;
+        }
+        let buffer = self.buffer_id;
+        self.load_window(id);
+        if self.buffer_id != buffer {
+            self.update_backlinks();
+        }
+    }
+
+    fn focus_neighbour(&mut self, dir: char) {
+        let rects = self.layout.rects(self.layout_area());
+        let at = (self.view.y + self.cursor.cx.saturating_sub(self.rowoffset), self.view.x + self.cursor_col().saturating_sub(self.coloffset));
+        if let Some(id) = neighbour(&rects, self.window_id, dir, at) {
+            self.focus_window(id);
+        }
+    }
+
+    fn focus_next(&mut self) {
+        let windows = self.editing_windows();
+        let pos = windows.iter().position(|id| *id == self.window_id).unwrap_or(0);
+        self.focus_window(windows[(pos + 1) % windows.len()]);
+    }
+
+    // Splits the focused window and moves into the new half, which shows
+    // `path` or else the same buffer.
+    fn split_window(&mut self, vertical: bool, path: Option<&Path>) {
+        if (vertical && self.view.w < 3) || (!vertical && self.view.h < 4) {
+            self.status = "Not enough room".to_string();
+            return;
+        }
+
+        let id = self.next_window_id;
+        self.next_window_id += 1;
+        self.windows.push(self.current_window());
+        self.layout.split(self.window_id, id, vertical);
+        self.window_id = id;
+        self.preview = None;
+
+        if let Some(path) = path {
+            if let Err(err) = self.open_note(path) {
+                self.status = err.to_string();
+            }
+        }
+    }
+
+    // Closes the focused window and its preview, and its tab page with it
+    // when it is the last.
+    fn close_window(&mut self) {
+        let windows = self.editing_windows();
+        if windows.len() < 2 && !self.tabs.is_empty() {
+            self.close_tab();
+            return;
+        }
+        if windows.len() < 2 {
+            self.status = "Cannot close the last window".to_string();
+            return;
+        }
+
+        let closing = self.window_id;
+        let pos = windows.iter().position(|id| *id == closing).unwrap_or(0);
+        self.focus_window(if pos > 0 { windows[pos - 1] } else { windows[1] });
+        for id in self.windows.iter().filter(|w| w.id == closing || w.preview == Some(closing)).map(|w| w.id).collect::<Vec<_>>() {
+            self.windows.retain(|w| w.id != id);
+            self.layout.remove(id);
+        }
+    }
+
+    // Windows of the tab page that can take the focus.
+    fn editing_windows(&self) -> Vec<usize> {
+        self.layout.windows().into_iter().filter(|id| !self.windows.iter().any(|w| w.id == *id && w.preview.is_some())).collect()
+    }
+
+    // Opens the rendered text of the focused window in a split to its right,
+    // or closes it again.
+    fn toggle_preview(&mut self) {
+        if let Some(id) = self.windows.iter().find(|w| w.preview == Some(self.window_id)).map(|w| w.id) {
+            self.windows.retain(|w| w.id != id);
+            self.layout.remove(id);
+            self.status = "-- NORMAL --".to_string();
+            return;
+        }
+
+        // The split leaves the focus in a new window on the left, so the
+        // current one becomes the preview
+        let source = self.window_id;
+        self.split_window(true, None);
+        if self.window_id == source {
+            return;
+        }
+        let window = self.window_id;
+        if let Some(w) = self.windows.iter_mut().find(|w| w.id == source) {
+            w.preview = Some(window);
+        }
+        self.status = "-- NORMAL --".to_string();
+    }
+
+    // Previews show the buffer and place of the window they follow.
+    fn sync_previews(&mut self) {
+        let (id, buffer, cursor, rowoffset) = (self.window_id, self.buffer_id, self.cursor, self.rowoffset);
+        for w in self.windows.iter_mut().filter(|w| w.preview == Some(id)) {
+            w.buffer = buffer;
+            w.cursor = cursor;
+            w.rowoffset = rowoffset;
+        }
+    }
+
+    // Draws the buffer fully rendered and wrapped, from the first line of the
+    // window it follows, scrolling on when that would leave its cursor line out.
+    fn render_preview(&mut self) {
+        let width = self.text_cols() as usize;
+        let height = self.text_rows() as usize;
+        let wrapped = |line: &Line| -> Vec<String> {
+            let render = render_text(line, Conceal::Full).0;
+            wrap_columns(&strip_escapes(&render), width, 0, true).into_iter().map(|(start, end)| slice_columns(&render, start, end - start)).collect()
+        };
+
+        let lines = self.num_rows as usize;
+        let cx = (self.cursor.cx as usize).min(lines.saturating_sub(1));
+        let top = preview_top(self.rowoffset as usize, cx, height, |l| if l < lines { wrapped(&self.rows[l]).len() } else { 0 });
+
+        let mut screen = Vec::new();
+        for line in self.rows.iter().take(lines).skip(top) {
+            if screen.len() >= height {
+                break;
+            }
+            screen.extend(wrapped(line));
+        }
+
+        for i in 0..height {
+            let text = screen.get(i).map_or("~", |row| row.as_str());
+            let padding = " ".repeat(width.saturating_sub(visible_width(text)));
+            self.term_buf.push_str(&format!("\x1b[{};{}H{}{}", self.view.y + i as u16 + 1, self.view.x + 1, text, padding));
+        }
+    }
+
+    fn only_window(&mut self) {
+        let windows = self.layout.windows();
+        self.windows.retain(|w| !windows.contains(&w.id));
+        self.layout = Layout::Window(self.window_id);
+    }
+
+    // Opens a tab page after the current one, with a single window showing
+    // `path` or an empty buffer.
+    fn new_tab(&mut self, path: Option<&Path>) {
+        let id = self.next_window_id;
+        self.next_window_id += 1;
+        self.windows.push(self.current_window());
+        let layout = std::mem::replace(&mut self.layout, Layout::Window(id));
+        self.tabs.insert(self.tab, Tab { layout, window: self.window_id });
+        self.tab += 1;
+        self.window_id = id;
+        self.preview = None;
+
+        self.next_buffer_id += 1;
+        self.show_buffer(Buffer { id: self.next_buffer_id - 1, rows: Vec::new(), num_rows: 0, filename: None, dirty: 0, cursor: Cursor { cx: 0, cy: 0 }, rowoffset: 0, coloffset: 0 });
+        self.status = "-- NORMAL --".to_string();
+        if let Some(path) = path {
+            if let Err(err) = self.open_note(path) {
+                self.status = err.to_string();
+            }
+        }
+    }
+
+    // Shows tab page `n`, counting round from the last to the first.
+    fn go_tab(&mut self, n: usize) {
+        let n = n % (self.tabs.len() + 1);
+        if n == self.tab {
+            return;
+        }
+
+        let layout = std::mem::replace(&mut self.layout, Layout::Window(self.window_id));
+        self.tabs.insert(self.tab, Tab { layout, window: self.window_id });
+        let tab = self.tabs.remove(n);
+        self.layout = tab.layout;
+        self.tab = n;
+        self.focus_window(tab.window);
+    }
+
+    // Closes the tab page and its windows, moving to the next page.
+    fn close_tab(&mut self) {
+        if self.tabs.is_empty() {
+            self.status = "Cannot close the last tab page".to_string();
+            return;
+        }
+
+        let windows = self.layout.windows();
+        let idx = self.tab.min(self.tabs.len() - 1);
+        let tab = self.tabs.remove(idx);
+        self.layout = tab.layout;
+        self.tab = idx;
+        self.focus_window(tab.window);
+        self.windows.retain(|w| !windows.contains(&w.id));
+    }
+
+    // The buffer name shown in window `id`, marked when it has unsaved changes.
+    fn window_label(&self, id: usize) -> String {
+        let buffer = self.windows.iter().find(|w| w.id == id).map_or(self.buffer_id, |w| w.buffer);
+        let (filename, dirty) = match self.buffers.iter().find(|b| b.id == buffer) {
+            Some(b) if buffer != self.buffer_id => (&b.filename, b.dirty),
+            _ => (&self.filename, self.dirty),
+        };
+
+        let name = filename.as_ref().and_then(|f| Path::new(f).file_name()).map_or("[No Name]".to_string(), |f| f.to_string_lossy().to_string());
+        if dirty != 0 { format!("{}+", name) } else { name }
+    }
+
+    // The tab pages along the top, each with the buffers its windows show.
+    // Like the status lines it is only there once there is a second page.
+    fn draw_tab_line(&mut self) {
+        if self.tabs.is_empty() {
+            return;
+        }
+
+        let pages: Vec<Vec<String>> = (0..=self.tabs.len()).map(|n| {
+            let windows = match n.cmp(&self.tab) {
+                std::cmp::Ordering::Less => self.tabs[n].layout.windows(),
+                std::cmp::Ordering::Equal => self.layout.windows(),
+                std::cmp::Ordering::Greater => self.tabs[n - 1].layout.windows(),
+            };
+            windows.into_iter().map(|id| self.window_label(id)).collect()
+        }).collect();
+
+        self.term_buf.push_str(&format!("\x1b[1;1H{}", tab_line(&pages, self.tab, self.size.0)));
+    }
+
+    // Collects the signs of the loaded buffer. Diagnostics win over
+    // bookmarks, then search matches, then git changes.
+    fn update_signs(&mut self) {
+        let mut signs = HashMap::new();
+        let changes = self.filename.as_ref().and_then(|filename| self.git_changes.get(filename));
+        for &(line, sign) in changes.into_iter().flatten() {
+            signs.insert(line, (sign, match sign { '+' => "32", '~' => "33", _ => "31" }));
+        }
+        if let Some(re) = &self.search {
+            for (line, row) in self.rows.iter().enumerate().take(self.num_rows as usize) {
+                if re.is_match(&row.row) {
+                    signs.insert(line, ('*', "36"));
+                }
+            }
+        }
+        for (_, mark, line) in self.marks.iter().filter(|(buffer, _, _)| *buffer == self.buffer_id) {
+            signs.insert(*line, (*mark, "35"));
+        }
+        for item in self.diagnostics.iter().filter(|item| item.file.as_ref().is_some_and(|f| self.is_current(f))) {
+            signs.insert(item.line, ('!', "1;31"));
+        }
+        self.signs = signs;
+    }
+
+    // Works out the lines changed in the saved file since the git index.
+    // Runs when a file is opened or saved rather than while drawing, so
+    // changes made to the repository outside the editor show after the
+    // next save.
+    fn load_git_changes(&mut self, filename: &str) {
+        let path = Path::new(filename);
+        let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        let output = Command::new("git")
+            .args(["diff", "-U0", "--no-color", "--no-ext-diff", "--"])
+            .arg(path.file_name().unwrap_or_default())
+            .current_dir(dir)
+            .output();
+
+        let changes = match output.ok().filter(|output| output.status.success()) {
+            Some(output) => diff_signs(&String::from_utf8_lossy(&output.stdout)),
+            None => Vec::new(),
+        };
+        self.git_changes.insert(filename.to_string(), changes);
+    }
+
+    fn set_mark(&mut self, mark: char) {
+        if !mark.is_ascii_lowercase() {
+            return;
+        }
+        self.marks.retain(|(buffer, m, _)| !(*buffer == self.buffer_id && *m == mark));
+        self.marks.push((self.buffer_id, mark, self.cursor.cx as usize));
+    }
+
+    // Keeps the marks of the loaded buffer on their lines when `removed`
+    // lines at `at` are replaced by `added` ones. Marks on lines that are
+    // deleted outright go with them.
+    fn shift_marks(&mut self, at: usize, removed: usize, added: usize) {
+        let buffer = self.buffer_id;
+        self.marks.retain_mut(|(b, _, line)| {
+            if *b != buffer {
+                return true;
+            }
+            match shifted_line(*line, at, removed, added) {
+                Some(moved) => {
+                    *line = moved;
+                    true
+                },
+                None => false,
+            }
+        });
+    }
+
+    fn jump_to_mark(&mut self, mark: char) {
+        match self.marks.iter().find(|(buffer, m, _)| *buffer == self.buffer_id && *m == mark) {
+            Some((_, _, line)) => {
+                self.jumps.push((self.buffer_id, self.cursor));
+                self.cursor.cx = (*line).min(self.num_rows.saturating_sub(1) as usize) as u16;
+                self.cursor.cy = 0;
+            },
+            None => self.status = format!("Mark not set: {}", mark),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn marks_follow_their_lines_through_edits() {
+        // Two lines inserted at 3
+        assert_eq!(shifted_line(2, 3, 0, 2), Some(2));
+        assert_eq!(shifted_line(3, 3, 0, 2), Some(5));
+        // Lines 3 and 4 deleted
+        assert_eq!(shifted_line(3, 3, 2, 0), None);
+        assert_eq!(shifted_line(4, 3, 2, 0), None);
+        assert_eq!(shifted_line(5, 3, 2, 0), Some(3));
+        // Three lines replaced by one, as when joining
+        assert_eq!(shifted_line(4, 3, 3, 1), Some(3));
+        assert_eq!(shifted_line(6, 3, 3, 1), Some(4));
+        // Two lines replaced by three keep their places
+        assert_eq!(shifted_line(4, 3, 2, 3), Some(4));
+    }
+
+    #[test]
+    fn columns_map_to_their_wrapped_row() {
+        let segments = [(0, 4), (4, 8), (8, 10)];
+        assert_eq!(segment_at(&segments, 0), 0);
+        assert_eq!(segment_at(&segments, 3), 0);
+        assert_eq!(segment_at(&segments, 4), 1);
+        assert_eq!(segment_at(&segments, 10), 2);
+    }
+
+    #[test]
+    fn diff_hunks_become_gutter_signs() {
+        let diff = "diff --git a/n.md b/n.md\n\
+                    @@ -2,0 +3,2 @@ intro\n+new\n+lines\n\
+                    @@ -7 +9 @@\n-old\n+changed\n\
+                    @@ -12,3 +13,0 @@\n-a\n-b\n-c\n\
+                    @@ -1 +0,0 @@\n-first\n";
+        assert_eq!(diff_signs(diff), vec![(2, '+'), (3, '+'), (8, '~'), (12, '_'), (0, '_')]);
+        assert_eq!(diff_signs(""), vec![]);
+    }
 }
@@ -0,0 +1,247 @@
+// The tree of splits that places windows on screen.
+
+use crate::list::truncate;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u16,
+    pub y: u16,
+    pub w: u16,
+    pub h: u16,
+}
+
+pub enum Layout {
+    Window(usize),
+    // Children side by side when `vertical`, otherwise stacked, each with its
+    // share of the space.
+    Split { vertical: bool, children: Vec<(f64, Layout)> },
+}
+
+impl Layout {
+    pub fn windows(&self) -> Vec<usize> {
+        self.rects(Rect { x: 0, y: 0, w: 0, h: 0 }).into_iter().map(|(id, _)| id).collect()
+    }
+
+    // Splits window `id` in two, putting window `new` above or to the left of it.
+    pub fn split(&mut self, id: usize, new: usize, vertical: bool) {
+        match self {
+            Layout::Window(w) if *w == id => {
+                *self = Layout::Split { vertical, children: vec![(1.0, Layout::Window(new)), (1.0, Layout::Window(id))] };
+            },
+            Layout::Window(_) => (),
+            Layout::Split { vertical: v, children } => {
+                // A split in the same direction just gains a sibling
+                let pos = children.iter().position(|(_, child)| matches!(child, Layout::Window(w) if *w == id));
+                if let Some(i) = pos.filter(|_| *v == vertical) {
+                    children[i].0 /= 2.0;
+                    children.insert(i, (children[i].0, Layout::Window(new)));
+                    return;
+                }
+                for (_, child) in children.iter_mut() {
+                    child.split(id, new, vertical);
+                }
+            },
+        }
+    }
+
+    // Drops window `id`, giving its space to its siblings.
+    pub fn remove(&mut self, id: usize) {
+        let Layout::Split { vertical, children } = self else {
+            return;
+        };
+
+        children.retain(|(_, child)| !matches!(child, Layout::Window(w) if *w == id));
+        let mut flat = Vec::new();
+        for (share, mut child) in children.drain(..) {
+            child.remove(id);
+            match child {
+                Layout::Split { vertical: v, children: inner } if v == *vertical => {
+                    let total: f64 = inner.iter().map(|(s, _)| s).sum();
+                    flat.extend(inner.into_iter().map(|(s, c)| (share * s / total, c)));
+                },
+                child => flat.push((share, child)),
+            }
+        }
+        *children = flat;
+
+        if children.len() == 1 {
+            let (_, only) = children.pop().unwrap();
+            *self = only;
+        }
+    }
+
+    // Gives every window in a split the same share.
+    pub fn equalize(&mut self) {
+        if let Layout::Split { children, .. } = self {
+            for (share, child) in children.iter_mut() {
+                *share = 1.0;
+                child.equalize();
+            }
+        }
+    }
+
+    // Where each window goes within `area`. Windows side by side are kept
+    // one column apart for the separator.
+    pub fn rects(&self, area: Rect) -> Vec<(usize, Rect)> {
+        let mut out = Vec::new();
+        self.place(area, &mut out);
+        out
+    }
+
+    fn place(&self, area: Rect, out: &mut Vec<(usize, Rect)>) {
+        let (vertical, children) = match self {
+            Layout::Window(id) => {
+                out.push((*id, area));
+                return;
+            },
+            Layout::Split { vertical, children } => (*vertical, children),
+        };
+
+        let gaps = if vertical { children.len() as u16 - 1 } else { 0 };
+        let total = if vertical { area.w } else { area.h }.saturating_sub(gaps);
+        let sum: f64 = children.iter().map(|(s, _)| s).sum();
+        let mut before = 0.0;
+        let mut start = 0;
+        let mut pos = if vertical { area.x } else { area.y };
+        for (share, child) in children {
+            before += share;
+            let end = ((total as f64 * before / sum).round() as u16).clamp(start, total);
+            let size = end - start;
+            start = end;
+            let rect = if vertical {
+                Rect { x: pos, y: area.y, w: size, h: area.h }
+            } else {
+                Rect { x: area.x, y: pos, w: area.w, h: size }
+            };
+            child.place(rect, out);
+            pos += size + if vertical { 1 } else { 0 };
+        }
+    }
+}
+
+// The window next to `from` in the direction of `h`, `j`, `k` or `l`,
+// preferring the one level with screen position `at`.
+pub fn neighbour(rects: &[(usize, Rect)], from: usize, dir: char, at: (u16, u16)) -> Option<usize> {
+    let (_, r) = *rects.iter().find(|(id, _)| *id == from)?;
+    let overlaps = |a: u16, alen: u16, b: u16, blen: u16| a < b + blen && b < a + alen;
+
+    let candidates: Vec<&(usize, Rect)> = rects.iter().filter(|(id, c)| *id != from && c.w > 0 && c.h > 0 && match dir {
+        'h' => c.x + c.w + 1 == r.x && overlaps(c.y, c.h, r.y, r.h),
+        'l' => r.x + r.w + 1 == c.x && overlaps(c.y, c.h, r.y, r.h),
+        'k' => c.y + c.h == r.y && overlaps(c.x, c.w, r.x, r.w),
+        'j' => r.y + r.h == c.y && overlaps(c.x, c.w, r.x, r.w),
+        _ => false,
+    }).collect();
+
+    let level = |c: &Rect| match dir {
+        'h' | 'l' => overlaps(c.y, c.h, at.0, 1),
+        _ => overlaps(c.x, c.w, at.1, 1),
+    };
+    candidates.iter().find(|(_, c)| level(c)).or(candidates.first()).map(|(id, _)| *id)
+}
+
+// The tab line, a label per tab page naming the buffers its windows show,
+// with the current page in bold and the rest of the `width` columns filled.
+pub fn tab_line(pages: &[Vec<String>], current: usize, width: u16) -> String {
+    let mut line = String::new();
+    let mut used = 0;
+    for (n, windows) in pages.iter().enumerate() {
+        let mut names: Vec<&str> = Vec::new();
+        for name in windows {
+            if !names.contains(&name.as_str()) {
+                names.push(name);
+            }
+        }
+
+        let label = truncate(&format!(" {} {} ", n + 1, names.join(" ")), width.saturating_sub(used));
+        used += label.chars().count() as u16;
+        line.push_str(if n == current { "\x1b[1m" } else { "\x1b[7m" });
+        line.push_str(&label);
+        line.push_str("\x1b[m");
+    }
+
+    format!("{}\x1b[7m{}\x1b[m", line, " ".repeat(width.saturating_sub(used) as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AREA: Rect = Rect { x: 0, y: 0, w: 81, h: 10 };
+
+    fn rect(x: u16, y: u16, w: u16, h: u16) -> Rect {
+        Rect { x, y, w, h }
+    }
+
+    // Window 1 on the left, 2 above 0 on the right.
+    fn three() -> Layout {
+        let mut layout = Layout::Window(0);
+        layout.split(0, 1, true);
+        layout.split(0, 2, false);
+        layout
+    }
+
+    #[test]
+    fn split_in_the_same_direction_adds_a_sibling() {
+        let mut layout = Layout::Window(0);
+        layout.split(0, 1, true);
+        layout.split(0, 2, true);
+        assert_eq!(layout.rects(AREA), vec![(1, rect(0, 0, 40, 10)), (2, rect(41, 0, 19, 10)), (0, rect(61, 0, 20, 10))]);
+    }
+
+    #[test]
+    fn split_across_nests() {
+        assert_eq!(three().rects(AREA), vec![(1, rect(0, 0, 40, 10)), (2, rect(41, 0, 40, 5)), (0, rect(41, 5, 40, 5))]);
+    }
+
+    #[test]
+    fn remove_gives_the_space_to_siblings() {
+        let mut layout = three();
+        layout.remove(2);
+        assert_eq!(layout.rects(AREA), vec![(1, rect(0, 0, 40, 10)), (0, rect(41, 0, 40, 10))]);
+        layout.remove(1);
+        assert!(matches!(layout, Layout::Window(0)));
+    }
+
+    #[test]
+    fn remove_flattens_splits_in_the_same_direction() {
+        let mut layout = three();
+        layout.split(2, 3, true);
+        layout.remove(0);
+        assert_eq!(layout.rects(AREA), vec![(1, rect(0, 0, 40, 10)), (3, rect(41, 0, 19, 10)), (2, rect(61, 0, 20, 10))]);
+    }
+
+    #[test]
+    fn neighbour_prefers_the_window_level_with_the_cursor() {
+        let rects = three().rects(AREA);
+        assert_eq!(neighbour(&rects, 0, 'k', (5, 50)), Some(2));
+        assert_eq!(neighbour(&rects, 0, 'h', (7, 50)), Some(1));
+        assert_eq!(neighbour(&rects, 1, 'l', (7, 10)), Some(0));
+        assert_eq!(neighbour(&rects, 1, 'l', (2, 10)), Some(2));
+        assert_eq!(neighbour(&rects, 1, 'j', (2, 10)), None);
+    }
+
+    fn pages(pages: &[&[&str]]) -> Vec<Vec<String>> {
+        pages.iter().map(|names| names.iter().map(|name| name.to_string()).collect()).collect()
+    }
+
+    #[test]
+    fn tab_line_names_each_buffer_once() {
+        let line = tab_line(&pages(&[&["a.md", "b.md+", "a.md"], &["[No Name]"]]), 1, 30);
+        assert_eq!(line, "\x1b[7m 1 a.md b.md+ \x1b[m\x1b[1m 2 [No Name] \x1b[m\x1b[7m   \x1b[m");
+    }
+
+    #[test]
+    fn tab_line_cuts_labels_at_the_edge() {
+        let line = tab_line(&pages(&[&["first.md"], &["second.md"]]), 0, 16);
+        assert_eq!(line, "\x1b[1m 1 first.md \x1b[m\x1b[7m 2 s\x1b[m\x1b[7m\x1b[m");
+    }
+
+    #[test]
+    fn tiny_areas_leave_windows_empty_rather_than_overflowing() {
+        let rects = three().rects(rect(0, 0, 1, 1));
+        assert!(rects.iter().all(|(_, r)| r.x + r.w <= 2 && r.y + r.h <= 1), "{:?}", rects);
+        assert!(three().rects(rect(0, 0, 0, 0)).iter().all(|(_, r)| r.w == 0 && r.h == 0));
+        assert_eq!(tab_line(&pages(&[&["a.md"], &["b.md"]]), 0, 0), "\x1b[1m\x1b[m\x1b[7m\x1b[m\x1b[7m\x1b[m");
+    }
+}
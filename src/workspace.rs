@@ -0,0 +1,575 @@
+// A workspace is a directory of notes marked by a `.next/` folder. Next keeps
+// an inverted index of the notes in `.next/index` so searching does not have
+// to read every file. Notes saved since the file was last written whole are
+// appended to it as they change.
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use ignore::overrides::OverrideBuilder;
+use ignore::WalkBuilder;
+
+use crate::markdown::{front_matter, tags};
+
+static INDEX_HEADER: &str = "next-index 1";
+
+// The closest directory above `start` holding a `.next/` folder.
+pub fn discover(start: &Path) -> Option<PathBuf> {
+    let start: PathBuf = if start.is_absolute() { start.to_path_buf() } else { env::current_dir().ok()?.join(start) };
+    let start: PathBuf = start.components().collect();
+    start.ancestors().find(|dir| dir.join(".next").is_dir()).map(|dir| dir.to_path_buf())
+}
+
+// Every markdown note below `root`, skipping hidden files and directories.
+pub fn note_files(root: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    walk(root, &mut files);
+    files.sort();
+    files
+}
+
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        if path.is_dir() {
+            walk(&path, files);
+        } else if path.extension().is_some_and(|ext| ext == "md") {
+            files.push(path);
+        }
+    }
+}
+
+// Every file below `root` that is not hidden or ignored by `.gitignore`.
+pub fn project_files(root: &Path) -> Vec<PathBuf> {
+    files_matching(root, None).unwrap_or_default()
+}
+
+// Like `project_files`, keeping only the files that match `glob`.
+pub fn files_matching(root: &Path, glob: Option<&str>) -> Result<Vec<PathBuf>, ignore::Error> {
+    // Matched after the walk: as walker overrides the glob would also pick up
+    // gitignored files.
+    let glob = match glob {
+        Some(glob) => Some(OverrideBuilder::new(root).add(glob)?.build()?),
+        None => None,
+    };
+
+    let mut files: Vec<PathBuf> = WalkBuilder::new(root)
+        .require_git(false)
+        .build()
+        .flatten()
+        .filter(|entry| entry.file_type().is_some_and(|t| t.is_file()))
+        .filter(|entry| glob.as_ref().is_none_or(|glob| !glob.matched(entry.path(), false).is_ignore()))
+        .map(|entry| entry.into_path())
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+// Title of a note: the front matter `title`, else its first level one heading.
+pub fn title(text: &str) -> Option<String> {
+    if let Some((_, values)) = front_matter(text).into_iter().find(|(key, values)| key == "title" && !values.is_empty()) {
+        return Some(values.join(", "));
+    }
+    text.lines().find_map(|line| line.strip_prefix("# ")).map(|title| title.trim().to_string())
+}
+
+// The title of the note at `path`, reading no further than its first heading.
+pub fn read_title(path: &Path) -> Option<String> {
+    let file = fs::File::open(path).ok()?;
+    let mut text = String::new();
+    let mut in_front = false;
+
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.ok()?;
+        match line.trim_end() {
+            "---" if i == 0 => in_front = true,
+            "---" | "..." if in_front => in_front = false,
+            _ => (),
+        }
+        let heading = !in_front && line.starts_with("# ");
+        text.push_str(&line);
+        text.push('\n');
+        if heading {
+            break;
+        }
+    }
+
+    title(&text)
+}
+
+pub fn tokens(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()).map(|w| w.to_lowercase())
+}
+
+fn modified(path: &Path) -> u64 {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+struct Doc {
+    path: String,
+    modified: u64,
+    len: usize,
+    // Every term of the note with how often it occurs.
+    terms: Vec<(String, u32)>,
+}
+
+pub struct Hit {
+    pub path: PathBuf,
+    pub score: f64,
+}
+
+pub struct Index {
+    pub root: PathBuf,
+    docs: Vec<Option<Doc>>,
+    ids: HashMap<String, usize>,
+    postings: HashMap<String, Vec<(usize, u32)>>,
+    // Notes changed or removed since the index file was last written.
+    pending: Vec<String>,
+    // Entries appended to the index file since it was written whole.
+    appended: usize,
+}
+
+impl Index {
+    // Loads the index of `root` and brings it up to date with the files on disk.
+    pub fn open(root: &Path) -> Index {
+        let mut index = Index::load(root).unwrap_or_else(|_| Index::empty(root));
+        if index.refresh() {
+            let _ = index.save();
+        }
+        index
+    }
+
+    fn empty(root: &Path) -> Index {
+        Index { root: root.to_path_buf(), docs: Vec::new(), ids: HashMap::new(), postings: HashMap::new(), pending: Vec::new(), appended: 0 }
+    }
+
+    fn file(root: &Path) -> PathBuf {
+        root.join(".next").join("index")
+    }
+
+    fn load(root: &Path) -> io::Result<Index> {
+        let text = fs::read_to_string(Index::file(root))?;
+        // A line cut short by a crash while appending is left out
+        let mut lines = text.split_inclusive('\n').filter_map(|line| line.strip_suffix('\n'));
+        if lines.next() != Some(INDEX_HEADER) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "unknown index format"));
+        }
+
+        let mut index = Index::empty(root);
+        for line in lines {
+            let fields: Vec<&str> = line.splitn(5, '\t').collect();
+            match fields.as_slice() {
+                ["D", id, modified, len, path] => {
+                    let id: usize = id.parse().unwrap_or(0);
+                    if index.docs.len() <= id {
+                        index.docs.resize_with(id + 1, || None);
+                    }
+                    index.docs[id] = Some(Doc { path: path.to_string(), modified: modified.parse().unwrap_or(0), len: len.parse().unwrap_or(0), terms: Vec::new() });
+                    index.ids.insert(path.to_string(), id);
+                },
+                ["T", term, postings] => {
+                    let postings: Vec<(usize, u32)> = postings.split(' ')
+                        .filter_map(|p| p.split_once(':'))
+                        .filter_map(|(id, tf)| Some((id.parse().ok()?, tf.parse().ok()?)))
+                        .filter(|(id, _)| matches!(index.docs.get(*id), Some(Some(_))))
+                        .collect();
+                    for (id, tf) in postings.iter() {
+                        if let Some(Some(doc)) = index.docs.get_mut(*id) {
+                            doc.terms.push((term.to_string(), *tf));
+                        }
+                    }
+                    index.postings.insert(term.to_string(), postings);
+                },
+                // Notes saved after the file was written, in order
+                ["U", modified, len, terms, path] => {
+                    let counts = terms.split(' ')
+                        .filter_map(|t| t.rsplit_once(':'))
+                        .filter_map(|(term, tf)| Some((term.to_string(), tf.parse().ok()?)))
+                        .collect();
+                    index.insert(path, counts, modified.parse().unwrap_or(0), len.parse().unwrap_or(0));
+                    index.appended += 1;
+                },
+                ["X", path] => {
+                    index.remove(path);
+                    index.appended += 1;
+                },
+                _ => (),
+            }
+        }
+        index.pending.clear();
+
+        Ok(index)
+    }
+
+    // Writes out the notes changed since the last save by appending them to
+    // the index file. The file is written whole instead when there is none
+    // yet or the appended entries have grown past half the notes.
+    pub fn save(&mut self) -> io::Result<()> {
+        let file = Index::file(&self.root);
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        if !file.exists() || self.appended + self.pending.len() > self.ids.len() / 2 + 64 {
+            return self.compact();
+        }
+
+        let mut out = String::new();
+        for name in std::mem::take(&mut self.pending) {
+            match self.ids.get(&name).and_then(|id| self.docs[*id].as_ref()) {
+                Some(doc) => {
+                    let terms: Vec<String> = doc.terms.iter().map(|(term, tf)| format!("{}:{}", term, tf)).collect();
+                    out.push_str(&format!("U\t{}\t{}\t{}\t{}\n", doc.modified, doc.len, terms.join(" "), doc.path));
+                },
+                None => out.push_str(&format!("X\t{}\n", name)),
+            }
+            self.appended += 1;
+        }
+        OpenOptions::new().append(true).open(file)?.write_all(out.as_bytes())
+    }
+
+    // Rewrites the index file whole, through a temporary file so a crash
+    // cannot leave it cut short.
+    fn compact(&mut self) -> io::Result<()> {
+        let mut out = String::from(INDEX_HEADER);
+        out.push('\n');
+
+        for (id, doc) in self.docs.iter().enumerate() {
+            if let Some(doc) = doc {
+                out.push_str(&format!("D\t{}\t{}\t{}\t{}\n", id, doc.modified, doc.len, doc.path));
+            }
+        }
+        for (term, postings) in self.postings.iter() {
+            let postings: Vec<String> = postings.iter().map(|(id, tf)| format!("{}:{}", id, tf)).collect();
+            out.push_str(&format!("T\t{}\t{}\n", term, postings.join(" ")));
+        }
+
+        fs::create_dir_all(self.root.join(".next"))?;
+        let file = Index::file(&self.root);
+        let tmp = file.with_extension("tmp");
+        fs::write(&tmp, out)?;
+        fs::rename(tmp, file)?;
+
+        self.pending.clear();
+        self.appended = 0;
+        Ok(())
+    }
+
+    // Reindexes notes changed since they were indexed and drops deleted ones.
+    // This only stats the notes, reading just those whose modification time
+    // moved, so notes edited outside Next are picked up once per session.
+    pub fn refresh(&mut self) -> bool {
+        let mut changed = false;
+        let mut seen = HashSet::new();
+
+        for path in note_files(&self.root) {
+            let Ok(name) = path.strip_prefix(&self.root) else {
+                continue;
+            };
+            let name = name.to_string_lossy().to_string();
+            let modified = modified(&path);
+            let current = self.ids.get(&name).and_then(|id| self.docs[*id].as_ref()).map(|doc| doc.modified);
+            if current != Some(modified) {
+                if let Ok(text) = fs::read_to_string(&path) {
+                    self.update(&name, &text, modified);
+                    changed = true;
+                }
+            }
+            seen.insert(name);
+        }
+
+        let gone: Vec<String> = self.ids.keys().filter(|name| !seen.contains(*name)).cloned().collect();
+        for name in gone {
+            self.remove(&name);
+            changed = true;
+        }
+
+        changed
+    }
+
+    // Path of `path` relative to the workspace root, as stored in the index.
+    pub fn relative(&self, path: &Path) -> Option<String> {
+        let root = fs::canonicalize(&self.root).ok()?;
+        let path = fs::canonicalize(path).ok()?;
+        path.strip_prefix(root).ok().map(|p| p.to_string_lossy().to_string())
+    }
+
+    pub fn update(&mut self, name: &str, text: &str, modified: u64) {
+        let mut counts: HashMap<String, u32> = HashMap::new();
+        let mut len = 0;
+        for token in tokens(text) {
+            *counts.entry(token).or_insert(0) += 1;
+            len += 1;
+        }
+        self.insert(name, counts.into_iter().collect(), modified, len);
+    }
+
+    fn insert(&mut self, name: &str, terms: Vec<(String, u32)>, modified: u64, len: usize) {
+        let id = self.remove(name).unwrap_or_else(|| {
+            self.docs.push(None);
+            self.docs.len() - 1
+        });
+        for (term, tf) in terms.iter() {
+            self.postings.entry(term.clone()).or_default().push((id, *tf));
+        }
+
+        self.docs[id] = Some(Doc { path: name.to_string(), modified, len, terms });
+        self.ids.insert(name.to_string(), id);
+        self.changed(name);
+    }
+
+    fn changed(&mut self, name: &str) {
+        if !self.pending.iter().any(|n| n == name) {
+            self.pending.push(name.to_string());
+        }
+    }
+
+    // Reindexes a note that was just written.
+    pub fn update_file(&mut self, path: &Path) -> bool {
+        let Some(name) = self.relative(path) else {
+            return false;
+        };
+        let modified = modified(path);
+        if self.ids.get(&name).and_then(|id| self.docs[*id].as_ref()).is_some_and(|doc| doc.modified == modified) {
+            return false;
+        }
+        match fs::read_to_string(path) {
+            Ok(text) => {
+                self.update(&name, &text, modified);
+                true
+            },
+            Err(_) => false,
+        }
+    }
+
+    // Drops a note from the index, returning the id it had.
+    fn remove(&mut self, name: &str) -> Option<usize> {
+        let id = self.ids.remove(name)?;
+        let doc = self.docs[id].take()?;
+        self.changed(name);
+
+        for (term, _) in doc.terms {
+            if let Some(postings) = self.postings.get_mut(&term) {
+                postings.retain(|(doc, _)| *doc != id);
+                if postings.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+        Some(id)
+    }
+
+    // Notes containing every word of `words`.
+    pub fn containing(&self, words: &str) -> Vec<PathBuf> {
+        let mut ids: Option<HashSet<usize>> = None;
+        for term in tokens(words) {
+            let found: HashSet<usize> = self.postings.get(&term).map(|p| p.iter().map(|(id, _)| *id).collect()).unwrap_or_default();
+            ids = Some(match ids {
+                Some(ids) => ids.intersection(&found).copied().collect(),
+                None => found,
+            });
+        }
+
+        let mut paths: Vec<PathBuf> = match ids {
+            Some(ids) => ids.into_iter().filter_map(|id| self.docs[id].as_ref()).map(|doc| self.root.join(&doc.path)).collect(),
+            None => self.docs.iter().flatten().map(|doc| self.root.join(&doc.path)).collect(),
+        };
+        paths.sort();
+        paths
+    }
+
+    // Notes matching any word of `query`, best first (BM25).
+    pub fn search(&self, query: &str) -> Vec<Hit> {
+        let docs: Vec<&Doc> = self.docs.iter().flatten().collect();
+        if docs.is_empty() {
+            return Vec::new();
+        }
+        let count = docs.len() as f64;
+        let avg = docs.iter().map(|doc| doc.len).sum::<usize>() as f64 / count;
+
+        let mut scores: HashMap<usize, f64> = HashMap::new();
+        for term in tokens(query) {
+            let Some(postings) = self.postings.get(&term) else {
+                continue;
+            };
+            let df = postings.len() as f64;
+            let idf = (1.0 + (count - df + 0.5) / (df + 0.5)).ln();
+            for (id, tf) in postings.iter() {
+                let Some(doc) = &self.docs[*id] else {
+                    continue;
+                };
+                let tf = *tf as f64;
+                let norm = 1.2 * (0.25 + 0.75 * doc.len as f64 / avg.max(1.0));
+                *scores.entry(*id).or_insert(0.0) += idf * tf * 2.2 / (tf + norm);
+            }
+        }
+
+        let mut hits: Vec<Hit> = scores.into_iter()
+            .filter_map(|(id, score)| self.docs[id].as_ref().map(|doc| Hit { path: self.root.join(&doc.path), score }))
+            .collect();
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.path.cmp(&b.path)));
+        hits
+    }
+}
+
+// The first line of `text` containing a word of `query`, as (line, column, text).
+pub fn snippet(text: &str, query: &str) -> Option<(usize, usize, String)> {
+    let terms: Vec<String> = tokens(query).collect();
+    text.lines().enumerate().find_map(|(i, line)| {
+        let lower = line.to_lowercase();
+        let col = terms.iter().filter_map(|term| lower.find(term.as_str())).min()?;
+        // Byte offsets can shift when lowercasing changes a character's length
+        let col = if line.is_char_boundary(col) { col } else { 0 };
+        Some((i, col, line.trim().to_string()))
+    })
+}
+
+// Whether a note matches every term of a query: `tag:name` (or `#name`)
+// for tags, `key:value` for front matter fields and plain words for text.
+pub fn matches_query(text: &str, terms: &[&str]) -> bool {
+    let fields = front_matter(text);
+    let tags: Vec<String> = tags(text).iter().map(|tag| tag.to_lowercase()).collect();
+    let words: HashSet<String> = tokens(text).collect();
+
+    terms.iter().all(|term| {
+        let term = term.to_lowercase();
+        match term.split_once(':') {
+            Some(("tag", tag)) => tags.iter().any(|t| t == tag.trim_start_matches('#')),
+            Some((key, value)) => fields.iter().any(|(k, values)| k.to_lowercase() == key && values.iter().any(|v| v.to_lowercase() == value)),
+            None if term.starts_with('#') => tags.iter().any(|t| *t == term[1..]),
+            // Words match whole, as the index matches them
+            None => tokens(&term).all(|word| words.contains(&word)),
+        }
+    })
+}
+
+// Words every note matching `terms` must contain, to narrow a query with the index.
+pub fn query_words(terms: &[&str]) -> String {
+    terms.iter().map(|term| match term.split_once(':') {
+        Some(("tag", tag)) => tag.to_string(),
+        Some((key, value)) => format!("{} {}", key, value),
+        None => term.to_string(),
+    }).collect::<Vec<String>>().join(" ")
+}
+
+// An empty workspace under the temp directory for tests, named after the
+// test so tests running side by side keep apart.
+#[cfg(test)]
+pub fn temp_workspace(name: &str) -> PathBuf {
+    let root = env::temp_dir().join(format!("next-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join(".next")).unwrap();
+    root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs_keep_gitignored_files_out() {
+        let root = temp_workspace("glob");
+        fs::write(root.join(".gitignore"), "secret.md\n").unwrap();
+        fs::write(root.join("a.md"), "").unwrap();
+        fs::write(root.join("b.txt"), "").unwrap();
+        fs::write(root.join("secret.md"), "").unwrap();
+        assert_eq!(files_matching(&root, Some("*.md")).unwrap(), vec![root.join("a.md")]);
+        assert_eq!(files_matching(&root, Some("!*.md")).unwrap(), vec![root.join("b.txt")]);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn read_title_stops_at_the_first_heading() {
+        let root = temp_workspace("title");
+        fs::write(root.join("a.md"), "intro\n# First\n# Second\n").unwrap();
+        fs::write(root.join("b.md"), "---\ntitle: Front\n---\n# Heading\n").unwrap();
+        fs::write(root.join("c.md"), "---\ntags: [x]\n---\nbody\n").unwrap();
+        assert_eq!(read_title(&root.join("a.md")).as_deref(), Some("First"));
+        assert_eq!(read_title(&root.join("b.md")).as_deref(), Some("Front"));
+        assert_eq!(read_title(&root.join("c.md")), None);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn saves_append_and_load_back() {
+        let root = temp_workspace("append");
+        fs::write(root.join("a.md"), "apple banana").unwrap();
+        fs::write(root.join("b.md"), "cherry").unwrap();
+        let mut index = Index::open(&root);
+
+        index.update("a.md", "apple durian", 5);
+        index.remove("b.md");
+        index.save().unwrap();
+        let text = fs::read_to_string(Index::file(&root)).unwrap();
+        assert!(text.lines().any(|line| line.starts_with("U\t5\t")));
+        assert!(text.lines().any(|line| line == "X\tb.md"));
+
+        let index = Index::load(&root).unwrap();
+        assert_eq!(index.containing("durian"), vec![root.join("a.md")]);
+        assert!(index.containing("banana").is_empty());
+        assert!(index.containing("cherry").is_empty());
+        assert_eq!(index.appended, 2);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn many_saves_rewrite_the_file_whole() {
+        let root = temp_workspace("compact");
+        fs::write(root.join("a.md"), "apple").unwrap();
+        let mut index = Index::open(&root);
+
+        for n in 0..100 {
+            index.update("a.md", &format!("word{}", n), n);
+            index.save().unwrap();
+        }
+        let text = fs::read_to_string(Index::file(&root)).unwrap();
+        assert!(text.lines().filter(|line| line.starts_with("U\t")).count() < 70);
+
+        let index = Index::load(&root).unwrap();
+        assert_eq!(index.containing("word99"), vec![root.join("a.md")]);
+        assert!(index.containing("word98").is_empty());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn queries_match_whole_words_like_the_index() {
+        let text = "---\nstatus: open\ntags: [work]\n---\nplanning the #roadmap";
+        assert!(!matches_query(text, &["plan"]));
+        assert!(matches_query(text, &["Planning", "tag:work", "#roadmap", "status:open"]));
+        assert!(!matches_query(text, &["status:closed"]));
+
+        let mut index = Index::empty(Path::new("/notes"));
+        index.update("a.md", text, 1);
+        assert!(index.containing(&query_words(&["plan"])).is_empty());
+        assert_eq!(index.containing(&query_words(&["planning", "tag:work", "status:open"])).len(), 1);
+    }
+
+    #[test]
+    fn a_line_cut_short_is_ignored() {
+        let root = temp_workspace("torn");
+        fs::write(root.join("a.md"), "apple").unwrap();
+        let mut index = Index::open(&root);
+        index.update("a.md", "banana", 7);
+        index.save().unwrap();
+
+        let mut text = fs::read_to_string(Index::file(&root)).unwrap();
+        text.push_str("U\t9\t1\tcherry:1\ta.m");
+        fs::write(Index::file(&root), text).unwrap();
+        let index = Index::load(&root).unwrap();
+        assert_eq!(index.containing("banana"), vec![root.join("a.md")]);
+        assert!(index.containing("cherry").is_empty());
+        fs::remove_dir_all(root).unwrap();
+    }
+}